@@ -0,0 +1,182 @@
+//! JWS algorithms accepted for ownership proofs.
+
+use josekit::{
+    jwk::{
+        alg::{ec::EcCurve, ed::EdCurve},
+        Jwk,
+    },
+    jws::{JwsSigner, JwsVerifier, EdDSA, ES256, ES384, ES512, PS256, RS256},
+    JoseError,
+};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProofAlg {
+    ES256,
+    ES384,
+    ES512,
+    EdDSA,
+    RS256,
+    PS256,
+}
+
+impl ProofAlg {
+    pub const ALL: [ProofAlg; 6] = [
+        ProofAlg::ES256,
+        ProofAlg::ES384,
+        ProofAlg::ES512,
+        ProofAlg::EdDSA,
+        ProofAlg::RS256,
+        ProofAlg::PS256,
+    ];
+
+    /// The JWS `alg` header value.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProofAlg::ES256 => "ES256",
+            ProofAlg::ES384 => "ES384",
+            ProofAlg::ES512 => "ES512",
+            ProofAlg::EdDSA => "EdDSA",
+            ProofAlg::RS256 => "RS256",
+            ProofAlg::PS256 => "PS256",
+        }
+    }
+
+    /// Checks that `jwk` has the key type and curve this algorithm signs with.
+    pub fn check_key(&self, jwk: &Jwk) -> Result<(), String> {
+        let kty = jwk.key_type();
+        let crv = jwk.curve();
+        let ok = match self {
+            ProofAlg::ES256 => kty == "EC" && crv == Some("P-256"),
+            ProofAlg::ES384 => kty == "EC" && crv == Some("P-384"),
+            ProofAlg::ES512 => kty == "EC" && crv == Some("P-521"),
+            ProofAlg::EdDSA => kty == "OKP" && matches!(crv, Some("Ed25519") | Some("Ed448")),
+            ProofAlg::RS256 | ProofAlg::PS256 => kty == "RSA",
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(format!(
+                "alg {} cannot be used with a {} key{}",
+                self.name(),
+                kty,
+                crv.map(|c| format!(" on curve {}", c)).unwrap_or_default()
+            ))
+        }
+    }
+
+    pub fn verifier_from_jwk(&self, jwk: &Jwk) -> Result<Box<dyn JwsVerifier>, JoseError> {
+        Ok(match self {
+            ProofAlg::ES256 => Box::new(ES256.verifier_from_jwk(jwk)?),
+            ProofAlg::ES384 => Box::new(ES384.verifier_from_jwk(jwk)?),
+            ProofAlg::ES512 => Box::new(ES512.verifier_from_jwk(jwk)?),
+            ProofAlg::EdDSA => Box::new(EdDSA.verifier_from_jwk(jwk)?),
+            ProofAlg::RS256 => Box::new(RS256.verifier_from_jwk(jwk)?),
+            ProofAlg::PS256 => Box::new(PS256.verifier_from_jwk(jwk)?),
+        })
+    }
+
+    pub fn signer_from_jwk(&self, jwk: &Jwk) -> Result<Box<dyn JwsSigner>, JoseError> {
+        Ok(match self {
+            ProofAlg::ES256 => Box::new(ES256.signer_from_jwk(jwk)?),
+            ProofAlg::ES384 => Box::new(ES384.signer_from_jwk(jwk)?),
+            ProofAlg::ES512 => Box::new(ES512.signer_from_jwk(jwk)?),
+            ProofAlg::EdDSA => Box::new(EdDSA.signer_from_jwk(jwk)?),
+            ProofAlg::RS256 => Box::new(RS256.signer_from_jwk(jwk)?),
+            ProofAlg::PS256 => Box::new(PS256.signer_from_jwk(jwk)?),
+        })
+    }
+}
+
+impl fmt::Display for ProofAlg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ProofAlg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ProofAlg::ALL
+            .iter()
+            .copied()
+            .find(|alg| alg.name() == s)
+            .ok_or_else(|| format!("unsupported alg {:?}", s))
+    }
+}
+
+/// Key types a holder can generate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    P256,
+    P384,
+    P521,
+    Ed25519,
+    Ed448,
+    Rsa2048,
+}
+
+impl KeyType {
+    pub const ALL: [KeyType; 6] = [
+        KeyType::P256,
+        KeyType::P384,
+        KeyType::P521,
+        KeyType::Ed25519,
+        KeyType::Ed448,
+        KeyType::Rsa2048,
+    ];
+
+    pub fn generate(&self) -> Result<Jwk, JoseError> {
+        match self {
+            KeyType::P256 => Jwk::generate_ec_key(EcCurve::P256),
+            KeyType::P384 => Jwk::generate_ec_key(EcCurve::P384),
+            KeyType::P521 => Jwk::generate_ec_key(EcCurve::P521),
+            KeyType::Ed25519 => Jwk::generate_ed_key(EdCurve::Ed25519),
+            KeyType::Ed448 => Jwk::generate_ed_key(EdCurve::Ed448),
+            KeyType::Rsa2048 => Jwk::generate_rsa_key(2048),
+        }
+    }
+
+    /// The algorithm a holder signs with by default for this key type.
+    pub fn default_alg(&self) -> ProofAlg {
+        match self {
+            KeyType::P256 => ProofAlg::ES256,
+            KeyType::P384 => ProofAlg::ES384,
+            KeyType::P521 => ProofAlg::ES512,
+            KeyType::Ed25519 | KeyType::Ed448 => ProofAlg::EdDSA,
+            KeyType::Rsa2048 => ProofAlg::RS256,
+        }
+    }
+
+    /// The name `kop keygen` accepts: the curve, or `RSA`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyType::P256 => "P-256",
+            KeyType::P384 => "P-384",
+            KeyType::P521 => "P-521",
+            KeyType::Ed25519 => "Ed25519",
+            KeyType::Ed448 => "Ed448",
+            KeyType::Rsa2048 => "RSA",
+        }
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KeyType::ALL
+            .iter()
+            .copied()
+            .find(|key_type| key_type.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unsupported key type {:?}", s))
+    }
+}
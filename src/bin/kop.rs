@@ -0,0 +1,342 @@
+//! `kop`, the holder command line: generates keys, mints ownership proofs
+//! and presents them to a verifier.
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose, Engine as _};
+use clap::{Parser, Subcommand, ValueEnum};
+use josekit::jwk::Jwk;
+use key_ownership_prover::algs::{KeyType, ProofAlg};
+use key_ownership_prover::holder::{self, Holder, KeyReference, ProofClaims, VerifierResponse};
+use key_ownership_prover::thumbprint;
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    name = "kop",
+    version,
+    about = "Prove ownership of a private key to a verifier"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a private key.
+    Keygen {
+        /// P-256, P-384, P-521, Ed25519, Ed448 or RSA (2048 bits).
+        #[arg(long = "type", default_value = "P-256")]
+        key_type: KeyType,
+        #[arg(long, value_enum, default_value_t = KeyFormat::Jwk)]
+        format: KeyFormat,
+        /// Where to write the private key. Without it, the private key is
+        /// printed instead of the public one.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Fetch a nonce from a verifier, sign a proof and present it.
+    Prove {
+        #[command(flatten)]
+        signing: SigningArgs,
+        /// Base URL of the verifier, also used as the proof's audience.
+        #[arg(long, env = "KOP_VERIFIER_URL")]
+        verifier: String,
+        /// Present a DPoP proof instead, which always embeds the JWK.
+        #[arg(long)]
+        dpop: bool,
+    },
+    /// Sign a proof for a nonce obtained out of band and print it.
+    SignOffline {
+        #[command(flatten)]
+        signing: SigningArgs,
+        #[arg(long)]
+        nonce: String,
+        /// The proof's `aud`, normally the verifier's URL.
+        #[arg(long)]
+        aud: Option<String>,
+    },
+    /// Decode a compact JWS without verifying it. Reads stdin for `-`.
+    Inspect { token: String },
+    /// Run every kind of proof against a verifier with throwaway keys.
+    Demo {
+        #[arg(
+            long,
+            env = "KOP_VERIFIER_URL",
+            default_value = "http://127.0.0.1:8080"
+        )]
+        verifier: String,
+        /// Also present a DPoP proof; the verifier must have DPoP enabled.
+        #[arg(long)]
+        dpop: bool,
+        /// The verifier's `REGISTRY_ADMIN_TOKEN`, for the scenarios that
+        /// enroll keys.
+        #[arg(long, env = "REGISTRY_ADMIN_TOKEN")]
+        admin_token: Option<String>,
+    },
+}
+
+#[derive(clap::Args)]
+struct SigningArgs {
+    /// Private key as a JWK or PKCS#8 PEM file.
+    #[arg(long)]
+    key: PathBuf,
+    /// Defaults to ES256/ES384/ES512, EdDSA or RS256 according to the key.
+    #[arg(long)]
+    alg: Option<ProofAlg>,
+    /// How the proof names its key: jwk, did:key or did:jwk.
+    #[arg(long = "key-ref", default_value = "jwk")]
+    key_reference: KeyReference,
+    /// The proof's `iss`.
+    #[arg(long)]
+    iss: Option<String>,
+}
+
+impl SigningArgs {
+    fn load(&self) -> anyhow::Result<(ProofAlg, Jwk)> {
+        let contents =
+            fs::read(&self.key).with_context(|| format!("reading {}", self.key.display()))?;
+        let private_key = holder::load_private_key(&contents)
+            .with_context(|| format!("loading {}", self.key.display()))?;
+        let alg = match self.alg {
+            Some(alg) => alg,
+            None => holder::default_alg(&private_key)
+                .ok_or_else(|| anyhow!("no default alg for this key, pass --alg"))?,
+        };
+        Ok((alg, private_key))
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum KeyFormat {
+    Jwk,
+    Pem,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<ExitCode> {
+    match Cli::parse().command {
+        Command::Keygen {
+            key_type,
+            format,
+            out,
+        } => keygen(key_type, format, out.as_deref()),
+        Command::Prove {
+            signing,
+            verifier,
+            dpop,
+        } => {
+            let (alg, private_key) = signing.load()?;
+            let mut holder = Holder::new(&verifier);
+            if let Some(iss) = signing.iss {
+                holder = holder.with_issuer(iss);
+            }
+            let response = if dpop {
+                holder.prove_dpop(alg, &private_key).await?
+            } else {
+                holder
+                    .prove(alg, &private_key, signing.key_reference)
+                    .await?
+            };
+            Ok(report(&response))
+        }
+        Command::SignOffline {
+            signing,
+            nonce,
+            aud,
+        } => {
+            let (alg, private_key) = signing.load()?;
+            let claims = ProofClaims {
+                audience: aud,
+                issuer: signing.iss,
+            };
+            let token =
+                holder::sign_proof(alg, &private_key, signing.key_reference, &nonce, &claims)?;
+            println!("{}", token);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Inspect { token } => inspect(&token),
+        Command::Demo {
+            verifier,
+            dpop,
+            admin_token,
+        } => Ok(demo(&verifier, dpop, admin_token).await),
+    }
+}
+
+fn keygen(key_type: KeyType, format: KeyFormat, out: Option<&Path>) -> anyhow::Result<ExitCode> {
+    let private_key = holder::generate_key(key_type)?;
+    let encoded = match format {
+        KeyFormat::Jwk => {
+            let mut json = serde_json::to_vec_pretty(private_key.as_ref())?;
+            json.push(b'\n');
+            json
+        }
+        KeyFormat::Pem => holder::private_key_pem(&private_key)?,
+    };
+    match out {
+        Some(path) => {
+            write_private(path, &encoded).with_context(|| format!("writing {}", path.display()))?;
+            let public_key = private_key.to_public_key()?;
+            println!("{}", serde_json::to_string_pretty(public_key.as_ref())?);
+        }
+        None => io::stdout().write_all(&encoded)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Creates `path` readable by its owner only, refusing to overwrite a key.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
+
+fn inspect(token: &str) -> anyhow::Result<ExitCode> {
+    let token = if token == "-" {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        input.trim().to_owned()
+    } else {
+        token.to_owned()
+    };
+    let header = key_ownership_prover::decode_jwt_header(&token)?;
+    let payload_part = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| anyhow!("JWT must have 3 parts"))?;
+    let payload: Value = serde_json::from_slice(
+        &general_purpose::URL_SAFE_NO_PAD
+            .decode(payload_part)
+            .context("decoding payload")?,
+    )
+    .context("parsing payload")?;
+
+    let mut report = json!({ "header": header, "payload": payload });
+    if let Some(Value::Object(jwk)) = header.get("jwk") {
+        let jkt = Jwk::from_map(jwk.clone())
+            .map_err(|e| e.to_string())
+            .and_then(|jwk| thumbprint::sha256_thumbprint(&jwk));
+        if let Ok(jkt) = jkt {
+            report["jwk_thumbprint"] = json!(jkt);
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    eprintln!("note: the signature has not been verified");
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Prints the verifier's answer and turns its status into the exit code.
+fn report(response: &VerifierResponse) -> ExitCode {
+    println!("{}", response.status);
+    if !response.body.is_null() {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&response.body).unwrap_or_default()
+        );
+    }
+    if response.status.is_success() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Proves ownership of a key of every type and key reference, several keys
+/// at once, a threshold of enrolled keys and a key rotation.
+async fn demo(verifier_url: &str, dpop: bool, admin_token: Option<String>) -> ExitCode {
+    let mut holder = Holder::new(verifier_url).with_issuer("demo-holder");
+    let enrolls = admin_token.is_some();
+    if let Some(token) = admin_token {
+        holder = holder.with_admin_token(token);
+    }
+    let mut failed = false;
+    let mut show = |label: &str, result: anyhow::Result<VerifierResponse>| match result {
+        Ok(response) => {
+            println!("{}: {}", label, response.status);
+            failed |= !response.status.is_success();
+        }
+        Err(e) => {
+            eprintln!("{}: {:#}", label, e);
+            failed = true;
+        }
+    };
+
+    let single = KeyType::ALL
+        .iter()
+        .map(|key_type| (*key_type, KeyReference::Jwk))
+        .chain([
+            (KeyType::P256, KeyReference::DidKey),
+            (KeyType::Ed25519, KeyReference::DidKey),
+            (KeyType::Ed25519, KeyReference::DidJwk),
+        ]);
+    for (key_type, key_reference) in single {
+        let result = async {
+            let private_key = key_type.generate()?;
+            holder
+                .prove(key_type.default_alg(), &private_key, key_reference)
+                .await
+        }
+        .await;
+        show(&format!("{} via {}", key_type, key_reference), result);
+    }
+
+    let result = async {
+        let keys = [
+            (ProofAlg::ES256, KeyType::P256.generate()?),
+            (ProofAlg::EdDSA, KeyType::Ed25519.generate()?),
+        ];
+        holder.prove_jws_json(&keys).await
+    }
+    .await;
+    show("JWS JSON with 2 keys", result);
+
+    if enrolls {
+        let result = async {
+            let mut keys = Vec::with_capacity(3);
+            for _ in 0..3 {
+                let private_key = KeyType::P256.generate()?;
+                let kid = holder.enroll("demo-quorum", &private_key).await?;
+                keys.push((ProofAlg::ES256, private_key, kid));
+            }
+            holder.prove_threshold(&keys, 2).await
+        }
+        .await;
+        show("threshold 2 of 3", result);
+
+        let result = async {
+            let old = (ProofAlg::ES256, KeyType::P256.generate()?);
+            let new = (ProofAlg::EdDSA, KeyType::Ed25519.generate()?);
+            let kid = holder.enroll("demo-rotation", &old.1).await?;
+            holder.rotate(&kid, &old, &new).await
+        }
+        .await;
+        show("key rotation", result);
+    } else {
+        println!("threshold and key rotation: skipped, pass --admin-token");
+    }
+
+    if dpop {
+        let result = async {
+            let private_key = KeyType::P256.generate()?;
+            holder.prove_dpop(ProofAlg::ES256, &private_key).await
+        }
+        .await;
+        show("DPoP", result);
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,134 @@
+//! Validation of registered JWT claims in ownership proofs.
+
+use josekit::jwt::JwtPayload;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How strictly `iat`, `nbf` and `exp` are checked.
+#[derive(Debug, Clone)]
+pub struct TimePolicy {
+    /// Reject proofs without an `iat`.
+    pub require_iat: bool,
+    /// Oldest `iat` accepted, measured back from now.
+    pub max_age: Duration,
+    /// Tolerated difference between the holder's clock and ours.
+    pub skew: Duration,
+}
+
+impl Default for TimePolicy {
+    fn default() -> Self {
+        TimePolicy {
+            require_iat: true,
+            max_age: Duration::from_secs(300),
+            skew: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Which verifier a proof must be addressed to, and who may have made it.
+#[derive(Debug, Clone, Default)]
+pub struct BindingPolicy {
+    /// Identifier or URL of this verifier that must appear in `aud`.
+    pub audience: Option<String>,
+    /// Reject proofs without an `iss`.
+    pub require_issuer: bool,
+    /// Accepted `iss` values. Empty means any issuer.
+    pub allowed_issuers: Vec<String>,
+}
+
+/// Checks `aud` and `iss` against `policy`.
+pub fn check_binding_claims(payload: &JwtPayload, policy: &BindingPolicy) -> Result<(), String> {
+    if let Some(expected) = &policy.audience {
+        match payload.audience() {
+            Some(audience) if audience.contains(&expected.as_str()) => {}
+            Some(_) => return Err(format!("proof is not addressed to {}", expected)),
+            None => return Err("aud missing in claims".to_string()),
+        }
+    }
+
+    match payload.issuer() {
+        Some(issuer)
+            if !policy.allowed_issuers.is_empty()
+                && !policy.allowed_issuers.iter().any(|allowed| allowed == issuer) =>
+        {
+            return Err(format!("issuer {:?} is not accepted", issuer));
+        }
+        Some(_) => {}
+        None if policy.require_issuer || !policy.allowed_issuers.is_empty() => {
+            return Err("iss missing in claims".to_string())
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
+/// Checks `iat` freshness, `nbf` and `exp` against `now`.
+pub fn check_time_claims(
+    payload: &JwtPayload,
+    policy: &TimePolicy,
+    now: SystemTime,
+) -> Result<(), String> {
+    match numeric_date(payload, "iat")? {
+        Some(iat) => {
+            if iat > add(now, policy.skew)? {
+                return Err("iat is in the future".to_string());
+            }
+            if add(add(iat, policy.max_age)?, policy.skew)? < now {
+                return Err("proof is too old".to_string());
+            }
+        }
+        None if policy.require_iat => return Err("iat missing in claims".to_string()),
+        None => {}
+    }
+
+    check_validity_period(payload, policy.skew, now)
+}
+
+/// Checks `nbf` and `exp` only, for long-lived tokens such as credentials
+/// where the age of `iat` does not matter.
+pub fn check_validity_period(
+    payload: &JwtPayload,
+    skew: Duration,
+    now: SystemTime,
+) -> Result<(), String> {
+    numeric_date(payload, "iat")?;
+
+    if let Some(nbf) = numeric_date(payload, "nbf")? {
+        if nbf > add(now, skew)? {
+            return Err("token is not yet valid".to_string());
+        }
+    }
+
+    if let Some(exp) = numeric_date(payload, "exp")? {
+        if add(exp, skew)? <= now {
+            return Err("token has expired".to_string());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a NumericDate claim. RFC 7519 allows fractional seconds; they are
+/// truncated. Values that do not fit a `SystemTime` are rejected here, as
+/// josekit's accessors would panic on them.
+fn numeric_date(payload: &JwtPayload, name: &str) -> Result<Option<SystemTime>, String> {
+    let value = match payload.claim(name) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let secs = match value.as_u64() {
+        Some(secs) => Some(secs),
+        None => value
+            .as_f64()
+            .filter(|secs| secs.is_finite() && *secs >= 0.0 && *secs < u64::MAX as f64)
+            .map(|secs| secs as u64),
+    };
+    secs.and_then(|secs| UNIX_EPOCH.checked_add(Duration::from_secs(secs)))
+        .map(Some)
+        .ok_or_else(|| format!("{} must be a NumericDate", name))
+}
+
+fn add(time: SystemTime, duration: Duration) -> Result<SystemTime, String> {
+    time.checked_add(duration)
+        .ok_or_else(|| "time claim is out of range".to_string())
+}
@@ -0,0 +1,654 @@
+//! Verifier settings: an optional TOML file, overridden by environment
+//! variables, validated as a whole at startup.
+
+use anyhow::Context;
+use key_ownership_prover::algs::ProofAlg;
+use key_ownership_prover::claims::{BindingPolicy, TimePolicy};
+use key_ownership_prover::remote_keys::RemoteKeyPolicy;
+use key_ownership_prover::store;
+use reqwest::Url;
+use serde::Deserialize;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DEFAULT_BIND: &str = "127.0.0.1:8080";
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_NONCE_TTL_SECS: u64 = 300;
+const DEFAULT_NONCE_STORE: &str = "memory";
+const DEFAULT_RECEIPT_TTL_SECS: u64 = 300;
+/// Upper bound on every lifetime and age setting, in seconds.
+const MAX_SECS: u64 = 24 * 60 * 60;
+const MAX_CLOCK_SKEW_SECS: u64 = 60 * 60;
+const MAX_REMOTE_KEYS_TIMEOUT_MS: u64 = 60_000;
+const MAX_REMOTE_KEYS_BYTES: u64 = 16 * 1024 * 1024;
+
+/// The config file as written. Every member is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    server: ServerSection,
+    proofs: ProofsSection,
+    nonces: NoncesSection,
+    receipts: ReceiptsSection,
+    tls: TlsSection,
+    registry: RegistrySection,
+    x5c: X5cSection,
+    remote_keys: RemoteKeysSection,
+    dpop: DpopSection,
+    oid4vci: Oid4vciSection,
+    sd_jwt: SdJwtSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    bind: Option<Vec<String>>,
+    log_level: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProofsSection {
+    allowed_algs: Option<Vec<String>>,
+    require_iat: Option<bool>,
+    max_age_secs: Option<u64>,
+    clock_skew_secs: Option<u64>,
+    audience: Option<String>,
+    require_iss: Option<bool>,
+    allowed_issuers: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct NoncesSection {
+    ttl_secs: Option<u64>,
+    store: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ReceiptsSection {
+    keys: Option<PathBuf>,
+    ttl_secs: Option<u64>,
+    issuer: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsSection {
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    client_ca: Option<PathBuf>,
+    client_auth: Option<String>,
+    require_proof_key_match: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RegistrySection {
+    file: Option<PathBuf>,
+    admin_token: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct X5cSection {
+    trust_anchors: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RemoteKeysSection {
+    allowed_hosts: Option<Vec<String>>,
+    allow_http: Option<bool>,
+    timeout_ms: Option<u64>,
+    max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DpopSection {
+    enabled: Option<bool>,
+    htu: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Oid4vciSection {
+    credential_issuer: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SdJwtSection {
+    issuer_keys: Option<PathBuf>,
+}
+
+/// Validated verifier settings.
+#[derive(Debug)]
+pub struct Config {
+    /// Addresses to listen on, as `host:port`.
+    pub bind: Vec<String>,
+    /// An `env_logger` filter, e.g. `info` or `warn,key_ownership_prover=debug`.
+    pub log_level: String,
+    pub allowed_algs: Vec<ProofAlg>,
+    pub time: TimePolicy,
+    pub binding: BindingPolicy,
+    pub nonce_ttl: Duration,
+    /// A [`store::open`] spec. It has been checked but not opened.
+    pub nonce_store: String,
+    /// Set when verification receipts are issued.
+    pub receipts: Option<ReceiptConfig>,
+    /// Set when every `bind` address serves HTTPS.
+    pub tls: Option<TlsConfig>,
+    /// JSON file enrolled keys persist to; in memory only when unset.
+    pub registry_file: Option<PathBuf>,
+    /// Bearer token for enrollment and key lookup, both disabled when unset.
+    pub registry_admin_token: Option<String>,
+    /// PEM bundle of the CAs `x5c` and `x5u` chains must lead to.
+    pub trust_anchors: Option<PathBuf>,
+    /// Set when `jku` and `x5u` keys may be fetched.
+    pub remote_keys: Option<RemoteKeyPolicy>,
+    /// Set when DPoP proofs are accepted.
+    pub dpop: Option<DpopConfig>,
+    /// Credential Issuer Identifier; OpenID4VCI proofs are refused when unset.
+    pub oid4vci_credential_issuer: Option<String>,
+    /// JWK Set of trusted SD-JWT issuers; SD-JWT presentations are refused
+    /// when unset.
+    pub sd_jwt_issuer_keys: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct DpopConfig {
+    /// The `htu` proofs must carry: the external URL of `/verify`.
+    pub htu: String,
+}
+
+#[derive(Debug)]
+pub struct ReceiptConfig {
+    /// JWK Set of receipt signing keys.
+    pub keys: PathBuf,
+    pub ttl: Duration,
+    pub issuer: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert: PathBuf,
+    /// PEM private key of the leaf.
+    pub key: PathBuf,
+    pub client_auth: ClientAuth,
+    /// Proofs at every proof endpoint must be signed with the client
+    /// certificate's key.
+    pub require_proof_key_match: bool,
+}
+
+/// Whether clients present certificates, and which CAs issue them.
+#[derive(Debug)]
+pub enum ClientAuth {
+    None,
+    /// Clients may present a certificate issued by a CA in the PEM bundle.
+    Optional(PathBuf),
+    /// Clients must present a certificate issued by a CA in the PEM bundle.
+    Required(PathBuf),
+}
+
+impl Config {
+    /// Reads the file at `path`, if any, applies the environment overrides
+    /// and validates the result, reporting every problem at once.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Config> {
+        let file = match path {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                toml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?
+            }
+            None => FileConfig::default(),
+        };
+        Config::resolve(file, |name| std::env::var(name).ok())
+    }
+
+    fn resolve(file: FileConfig, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Config> {
+        let mut errors = Vec::new();
+
+        let bind = env("BIND_ADDRS")
+            .map(|list| split_list(&list))
+            .or(file.server.bind)
+            .unwrap_or_else(|| vec![DEFAULT_BIND.to_string()]);
+        if bind.is_empty() {
+            errors.push("server.bind: at least one address is required".to_string());
+        }
+        for addr in &bind {
+            if let Err(e) = addr.to_socket_addrs() {
+                errors.push(format!("server.bind: {:?}: {}", addr, e));
+            }
+        }
+
+        let log_level = env("LOG_LEVEL")
+            .or(file.server.log_level)
+            .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+        if let Err(e) = check_log_filter(&log_level) {
+            errors.push(format!("server.log_level: {}", e));
+        }
+
+        let (time, binding) = resolve_claims(&file.proofs, &env, &mut errors);
+        let alg_names = env("ALLOWED_ALGS")
+            .map(|list| split_list(&list))
+            .or(file.proofs.allowed_algs);
+        let allowed_algs = match alg_names {
+            Some(names) => {
+                let parsed: Result<Vec<ProofAlg>, String> =
+                    names.iter().map(|name| name.parse()).collect();
+                match parsed {
+                    Ok(algs) if algs.is_empty() => {
+                        errors.push("proofs.allowed_algs: must not be empty".to_string());
+                        Vec::new()
+                    }
+                    Ok(algs) => algs,
+                    Err(e) => {
+                        errors.push(format!("proofs.allowed_algs: {}", e));
+                        Vec::new()
+                    }
+                }
+            }
+            None => ProofAlg::ALL.to_vec(),
+        };
+
+        let nonce_ttl = secs(
+            "nonces.ttl_secs",
+            env("NONCE_TTL_SECS"),
+            file.nonces.ttl_secs,
+            DEFAULT_NONCE_TTL_SECS,
+            MAX_SECS,
+            &mut errors,
+        );
+        let nonce_store = env("NONCE_STORE")
+            .or(file.nonces.store)
+            .unwrap_or_else(|| DEFAULT_NONCE_STORE.to_string());
+        if let Err(e) = store::check(&nonce_store) {
+            errors.push(format!("nonces.store: {:#}", e));
+        }
+
+        let receipt_ttl = secs(
+            "receipts.ttl_secs",
+            env("RECEIPT_TTL_SECS"),
+            file.receipts.ttl_secs,
+            DEFAULT_RECEIPT_TTL_SECS,
+            MAX_SECS,
+            &mut errors,
+        );
+        let receipts = env("RECEIPT_KEYS")
+            .map(PathBuf::from)
+            .or(file.receipts.keys)
+            .map(|keys| ReceiptConfig {
+                keys,
+                ttl: receipt_ttl,
+                issuer: env("RECEIPT_ISSUER").or(file.receipts.issuer),
+            });
+        if let Some(receipts) = &receipts {
+            if !receipts.keys.is_file() {
+                errors.push(format!(
+                    "receipts.keys: {} is not a file",
+                    receipts.keys.display()
+                ));
+            }
+        }
+
+        let tls = resolve_tls(file.tls, &env, &mut errors);
+
+        let registry_file = env("KEY_REGISTRY_FILE")
+            .map(PathBuf::from)
+            .or(file.registry.file);
+        let registry_admin_token = env("REGISTRY_ADMIN_TOKEN").or(file.registry.admin_token);
+        if registry_admin_token.as_deref() == Some("") {
+            errors.push("registry.admin_token: must not be empty".to_string());
+        }
+
+        let trust_anchors = env("X5C_TRUST_ANCHORS")
+            .map(PathBuf::from)
+            .or(file.x5c.trust_anchors);
+        let remote_keys = resolve_remote_keys(file.remote_keys, &env, &mut errors);
+
+        let dpop_enabled = flag(
+            "dpop.enabled",
+            env("DPOP_ENABLED"),
+            file.dpop.enabled,
+            false,
+            &mut errors,
+        );
+        let dpop_htu = env("DPOP_HTU").or(file.dpop.htu);
+        let dpop = match (dpop_enabled, dpop_htu) {
+            (true, Some(htu)) => {
+                if let Err(e) = check_htu(&htu) {
+                    errors.push(format!("dpop.htu: {}", e));
+                }
+                Some(DpopConfig { htu })
+            }
+            (true, None) => {
+                errors.push("dpop.htu: required when dpop.enabled is true".to_string());
+                None
+            }
+            (false, Some(_)) => {
+                errors.push("dpop.htu: set, but dpop.enabled is false".to_string());
+                None
+            }
+            (false, None) => None,
+        };
+
+        let oid4vci_credential_issuer =
+            env("OID4VCI_CREDENTIAL_ISSUER").or(file.oid4vci.credential_issuer);
+        let sd_jwt_issuer_keys = env("SD_JWT_ISSUER_KEYS")
+            .map(PathBuf::from)
+            .or(file.sd_jwt.issuer_keys);
+        if sd_jwt_issuer_keys.is_some() && binding.audience.is_none() {
+            errors.push("sd_jwt.issuer_keys: needs proofs.audience".to_string());
+        }
+        for (name, path) in [
+            ("x5c.trust_anchors", &trust_anchors),
+            ("sd_jwt.issuer_keys", &sd_jwt_issuer_keys),
+        ] {
+            if let Some(path) = path {
+                if !path.is_file() {
+                    errors.push(format!("{}: {} is not a file", name, path.display()));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("invalid configuration:\n  {}", errors.join("\n  "));
+        }
+        Ok(Config {
+            bind,
+            log_level,
+            allowed_algs,
+            time,
+            binding,
+            nonce_ttl,
+            nonce_store,
+            receipts,
+            tls,
+            registry_file,
+            registry_admin_token,
+            trust_anchors,
+            remote_keys,
+            dpop,
+            oid4vci_credential_issuer,
+            sd_jwt_issuer_keys,
+        })
+    }
+}
+
+/// The `htu` is compared with what clients sign, so it must be an absolute
+/// HTTP(S) URL without query or fragment.
+fn check_htu(htu: &str) -> Result<(), String> {
+    let url = Url::parse(htu).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("{} is not an http or https URL", htu));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!("{} must not have a query or fragment", htu));
+    }
+    Ok(())
+}
+
+fn resolve_claims(
+    file: &ProofsSection,
+    env: &impl Fn(&str) -> Option<String>,
+    errors: &mut Vec<String>,
+) -> (TimePolicy, BindingPolicy) {
+    let defaults = TimePolicy::default();
+    let time = TimePolicy {
+        require_iat: flag(
+            "proofs.require_iat",
+            env("REQUIRE_IAT"),
+            file.require_iat,
+            defaults.require_iat,
+            errors,
+        ),
+        max_age: secs(
+            "proofs.max_age_secs",
+            env("PROOF_MAX_AGE_SECS"),
+            file.max_age_secs,
+            defaults.max_age.as_secs(),
+            MAX_SECS,
+            errors,
+        ),
+        skew: Duration::from_secs(number(
+            "proofs.clock_skew_secs",
+            env("CLOCK_SKEW_SECS"),
+            file.clock_skew_secs,
+            defaults.skew.as_secs(),
+            0..=MAX_CLOCK_SKEW_SECS,
+            errors,
+        )),
+    };
+
+    let audience = env("PROOF_AUDIENCE").or_else(|| file.audience.clone());
+    if audience.as_deref() == Some("") {
+        errors.push("proofs.audience: must not be empty".to_string());
+    }
+    let binding = BindingPolicy {
+        audience,
+        require_issuer: flag(
+            "proofs.require_iss",
+            env("REQUIRE_ISS"),
+            file.require_iss,
+            false,
+            errors,
+        ),
+        allowed_issuers: env("ALLOWED_ISSUERS")
+            .map(|list| split_list(&list))
+            .or_else(|| file.allowed_issuers.clone())
+            .unwrap_or_default(),
+    };
+    (time, binding)
+}
+
+/// Remote keys are only fetched when `allowed_hosts` names at least one host.
+fn resolve_remote_keys(
+    file: RemoteKeysSection,
+    env: &impl Fn(&str) -> Option<String>,
+    errors: &mut Vec<String>,
+) -> Option<RemoteKeyPolicy> {
+    let allowed_hosts = env("REMOTE_KEYS_ALLOWED_HOSTS")
+        .map(|list| split_list(&list))
+        .or(file.allowed_hosts)
+        .unwrap_or_default();
+    let defaults = RemoteKeyPolicy::default();
+    let allow_http = flag(
+        "remote_keys.allow_http",
+        env("REMOTE_KEYS_ALLOW_HTTP"),
+        file.allow_http,
+        defaults.allow_http,
+        errors,
+    );
+    let timeout_ms = number(
+        "remote_keys.timeout_ms",
+        env("REMOTE_KEYS_TIMEOUT_MS"),
+        file.timeout_ms,
+        defaults.timeout.as_millis() as u64,
+        1..=MAX_REMOTE_KEYS_TIMEOUT_MS,
+        errors,
+    );
+    let max_bytes = number(
+        "remote_keys.max_bytes",
+        env("REMOTE_KEYS_MAX_BYTES"),
+        file.max_bytes,
+        defaults.max_bytes as u64,
+        1..=MAX_REMOTE_KEYS_BYTES,
+        errors,
+    );
+    if allowed_hosts.is_empty() {
+        return None;
+    }
+    Some(RemoteKeyPolicy {
+        allowed_hosts,
+        allow_http,
+        timeout: Duration::from_millis(timeout_ms),
+        max_bytes: max_bytes as usize,
+        ..defaults
+    })
+}
+
+fn resolve_tls(
+    file: TlsSection,
+    env: &impl Fn(&str) -> Option<String>,
+    errors: &mut Vec<String>,
+) -> Option<TlsConfig> {
+    let cert = env("TLS_CERT").map(PathBuf::from).or(file.cert);
+    let key = env("TLS_KEY").map(PathBuf::from).or(file.key);
+    let client_ca = env("TLS_CLIENT_CA").map(PathBuf::from).or(file.client_ca);
+    let client_auth = env("TLS_CLIENT_AUTH").or(file.client_auth);
+    let require_proof_key_match = flag(
+        "tls.require_proof_key_match",
+        env("TLS_REQUIRE_PROOF_KEY_MATCH"),
+        file.require_proof_key_match,
+        false,
+        errors,
+    );
+
+    let (cert, key) = match (cert, key) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) => {
+            if client_ca.is_some() || client_auth.is_some() || require_proof_key_match {
+                errors.push("tls: client certificates need tls.cert and tls.key".to_string());
+            }
+            return None;
+        }
+        _ => {
+            errors.push("tls: cert and key must be set together".to_string());
+            return None;
+        }
+    };
+    for (name, path) in [("tls.cert", &cert), ("tls.key", &key)] {
+        if !path.is_file() {
+            errors.push(format!("{}: {} is not a file", name, path.display()));
+        }
+    }
+
+    let default_auth = if client_ca.is_some() {
+        "required"
+    } else {
+        "none"
+    };
+    let client_auth = match (client_auth.as_deref().unwrap_or(default_auth), client_ca) {
+        ("none", None) => ClientAuth::None,
+        ("none", Some(_)) => {
+            errors.push("tls.client_ca: set, but tls.client_auth is \"none\"".to_string());
+            ClientAuth::None
+        }
+        ("optional" | "required", None) => {
+            errors.push("tls.client_auth: client certificates need tls.client_ca".to_string());
+            ClientAuth::None
+        }
+        ("optional", Some(ca)) => ClientAuth::Optional(ca),
+        ("required", Some(ca)) => ClientAuth::Required(ca),
+        (other, _) => {
+            errors.push(format!(
+                "tls.client_auth: {:?} is not one of none, optional or required",
+                other
+            ));
+            ClientAuth::None
+        }
+    };
+    if let ClientAuth::Optional(ca) | ClientAuth::Required(ca) = &client_auth {
+        if !ca.is_file() {
+            errors.push(format!("tls.client_ca: {} is not a file", ca.display()));
+        }
+    }
+    if require_proof_key_match && !matches!(client_auth, ClientAuth::Required(_)) {
+        errors
+            .push("tls.require_proof_key_match: needs tls.client_auth = \"required\"".to_string());
+    }
+
+    Some(TlsConfig {
+        cert,
+        key,
+        client_auth,
+        require_proof_key_match,
+    })
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// A number within `range`, from the environment, the file or the default.
+fn number(
+    name: &str,
+    env: Option<String>,
+    file: Option<u64>,
+    default: u64,
+    range: RangeInclusive<u64>,
+    errors: &mut Vec<String>,
+) -> u64 {
+    let value = match env {
+        Some(value) => match value.parse::<u64>() {
+            Ok(number) => number,
+            Err(_) => {
+                errors.push(format!("{}: {:?} is not a whole number", name, value));
+                return default;
+            }
+        },
+        None => file.unwrap_or(default),
+    };
+    if !range.contains(&value) {
+        errors.push(format!(
+            "{}: {} is not between {} and {}",
+            name,
+            value,
+            range.start(),
+            range.end()
+        ));
+        return default;
+    }
+    value
+}
+
+/// A positive number of seconds, at most `max`.
+fn secs(
+    name: &str,
+    env: Option<String>,
+    file: Option<u64>,
+    default: u64,
+    max: u64,
+    errors: &mut Vec<String>,
+) -> Duration {
+    Duration::from_secs(number(name, env, file, default, 1..=max, errors))
+}
+
+/// `true`/`1` or `false`/`0`, from the environment, the file or the default.
+fn flag(
+    name: &str,
+    env: Option<String>,
+    file: Option<bool>,
+    default: bool,
+    errors: &mut Vec<String>,
+) -> bool {
+    match env.as_deref() {
+        Some("true" | "1") => true,
+        Some("false" | "0") => false,
+        Some(other) => {
+            errors.push(format!("{}: {:?} is not true or false", name, other));
+            default
+        }
+        None => file.unwrap_or(default),
+    }
+}
+
+/// Checks an `env_logger` filter with the parser `env_logger` itself uses,
+/// which would only warn about a bad directive at startup and ignore it.
+fn check_log_filter(filter: &str) -> Result<(), String> {
+    env_filter::Builder::new()
+        .try_parse(filter)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,158 @@
+//! `did:key` and `did:jwk` holder identifiers, resolved without network access.
+
+use base64::{engine::general_purpose, Engine as _};
+use josekit::jwk::Jwk;
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use serde_json::{json, Map, Value};
+
+/// Multicodec prefixes (unsigned varints) of the supported public key types.
+const ED25519_PUB: [u8; 2] = [0xed, 0x01];
+const ED448_PUB: [u8; 2] = [0x83, 0x24];
+const P256_PUB: [u8; 2] = [0x80, 0x24];
+const P384_PUB: [u8; 2] = [0x81, 0x24];
+
+/// A DID and the public key it encodes.
+#[derive(Debug, Clone)]
+pub struct ResolvedDid {
+    pub did: String,
+    pub jwk: Map<String, Value>,
+}
+
+/// Returns true if `kid` names a DID rather than a registry entry.
+pub fn is_did(kid: &str) -> bool {
+    kid.starts_with("did:")
+}
+
+/// Resolves a `did:key` or `did:jwk` DID, or a DID URL whose fragment
+/// refers to the DID's single verification method.
+pub fn resolve(did_url: &str) -> Result<ResolvedDid, String> {
+    let (did, fragment) = match did_url.split_once('#') {
+        Some((did, fragment)) => (did, Some(fragment)),
+        None => (did_url, None),
+    };
+    let jwk = if let Some(msid) = did.strip_prefix("did:key:") {
+        if fragment.is_some_and(|f| f != msid) {
+            return Err("did:key fragment must repeat the method-specific id".to_string());
+        }
+        did_key_to_jwk(msid)?
+    } else if let Some(msid) = did.strip_prefix("did:jwk:") {
+        if fragment.is_some_and(|f| f != "0") {
+            return Err("did:jwk fragment must be #0".to_string());
+        }
+        did_jwk_to_jwk(msid)?
+    } else {
+        return Err(format!("unsupported DID method in {:?}", did));
+    };
+    Ok(ResolvedDid {
+        did: did.to_owned(),
+        jwk,
+    })
+}
+
+fn did_key_to_jwk(msid: &str) -> Result<Map<String, Value>, String> {
+    let encoded = msid
+        .strip_prefix('z')
+        .ok_or_else(|| "did:key must use base58btc multibase".to_string())?;
+    let bytes = bs58::decode(encoded)
+        .into_vec()
+        .map_err(|e| format!("invalid did:key encoding: {}", e))?;
+    if bytes.len() < 2 {
+        return Err("did:key is too short".to_string());
+    }
+    let (codec, key) = bytes.split_at(2);
+    let b64 = |bytes: &[u8]| general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    let jwk = match [codec[0], codec[1]] {
+        ED25519_PUB if key.len() == 32 => json!({ "kty": "OKP", "crv": "Ed25519", "x": b64(key) }),
+        ED448_PUB if key.len() == 57 => json!({ "kty": "OKP", "crv": "Ed448", "x": b64(key) }),
+        P256_PUB => {
+            let point = p256::PublicKey::from_sec1_bytes(key)
+                .map_err(|_| "invalid P-256 key in did:key".to_string())?
+                .to_encoded_point(false);
+            ec_jwk("P-256", point.as_bytes())
+        }
+        P384_PUB => {
+            let point = p384::PublicKey::from_sec1_bytes(key)
+                .map_err(|_| "invalid P-384 key in did:key".to_string())?
+                .to_encoded_point(false);
+            ec_jwk("P-384", point.as_bytes())
+        }
+        _ => return Err("unsupported did:key key type".to_string()),
+    };
+    into_map(jwk)
+}
+
+/// Builds an EC JWK from an uncompressed SEC1 point.
+fn ec_jwk(crv: &str, point: &[u8]) -> Value {
+    let size = (point.len() - 1) / 2;
+    json!({
+        "kty": "EC",
+        "crv": crv,
+        "x": general_purpose::URL_SAFE_NO_PAD.encode(&point[1..1 + size]),
+        "y": general_purpose::URL_SAFE_NO_PAD.encode(&point[1 + size..]),
+    })
+}
+
+fn did_jwk_to_jwk(msid: &str) -> Result<Map<String, Value>, String> {
+    let bytes = general_purpose::URL_SAFE_NO_PAD
+        .decode(msid)
+        .map_err(|e| format!("invalid did:jwk encoding: {}", e))?;
+    serde_json::from_slice(&bytes).map_err(|e| format!("did:jwk is not a JWK: {}", e))
+}
+
+fn into_map(value: Value) -> Result<Map<String, Value>, String> {
+    match value {
+        Value::Object(map) => Ok(map),
+        _ => Err("expected a JSON object".to_string()),
+    }
+}
+
+fn jwk_bytes(jwk: &Jwk, name: &str) -> Result<Vec<u8>, String> {
+    jwk.parameter(name)
+        .and_then(Value::as_str)
+        .and_then(|v| general_purpose::URL_SAFE_NO_PAD.decode(v).ok())
+        .ok_or_else(|| format!("JWK member {} is missing or invalid", name))
+}
+
+/// Encodes a public key as a `did:key` DID URL (`did:key:z...#z...`).
+pub fn did_key_url(jwk: &Jwk) -> Result<String, String> {
+    let (codec, key) = match (jwk.key_type(), jwk.curve()) {
+        ("OKP", Some("Ed25519")) => (ED25519_PUB, jwk_bytes(jwk, "x")?),
+        ("OKP", Some("Ed448")) => (ED448_PUB, jwk_bytes(jwk, "x")?),
+        ("EC", Some(crv @ "P-256")) | ("EC", Some(crv @ "P-384")) => {
+            let mut sec1 = vec![0x04];
+            sec1.extend(jwk_bytes(jwk, "x")?);
+            sec1.extend(jwk_bytes(jwk, "y")?);
+            if crv == "P-256" {
+                let key = p256::PublicKey::from_sec1_bytes(&sec1)
+                    .map_err(|_| "invalid P-256 key".to_string())?;
+                (P256_PUB, key.to_encoded_point(true).as_bytes().to_vec())
+            } else {
+                let key = p384::PublicKey::from_sec1_bytes(&sec1)
+                    .map_err(|_| "invalid P-384 key".to_string())?;
+                (P384_PUB, key.to_encoded_point(true).as_bytes().to_vec())
+            }
+        }
+        (kty, crv) => {
+            return Err(format!(
+                "did:key does not support {} keys{}",
+                kty,
+                crv.map(|c| format!(" on {}", c)).unwrap_or_default()
+            ))
+        }
+    };
+    let mut bytes = codec.to_vec();
+    bytes.extend(key);
+    let msid = format!("z{}", bs58::encode(bytes).into_string());
+    Ok(format!("did:key:{}#{}", msid, msid))
+}
+
+/// Encodes a public key as a `did:jwk` DID URL (`did:jwk:...#0`).
+pub fn did_jwk_url(jwk: &Jwk) -> Result<String, String> {
+    let public = jwk.to_public_key().map_err(|e| e.to_string())?;
+    let map: &Map<String, Value> = public.as_ref();
+    let encoded = serde_json::to_vec(map).map_err(|e| e.to_string())?;
+    Ok(format!(
+        "did:jwk:{}#0",
+        general_purpose::URL_SAFE_NO_PAD.encode(encoded)
+    ))
+}
@@ -0,0 +1,196 @@
+//! OAuth 2.0 Demonstrating Proof of Possession (RFC 9449).
+
+use crate::algs::ProofAlg;
+use base64::{engine::general_purpose, Engine as _};
+use josekit::{
+    jwk::Jwk,
+    jws::JwsHeader,
+    jwt::{self, JwtPayload},
+    JoseError,
+};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+pub const DPOP_TYPE: &str = "dpop+jwt";
+pub const DPOP_HEADER: &str = "DPoP";
+pub const DPOP_NONCE_HEADER: &str = "DPoP-Nonce";
+
+#[derive(Debug)]
+pub enum DpopError {
+    /// The proof is malformed or does not match the request it accompanies.
+    InvalidProof(String),
+    /// The proof lacks a valid server-issued nonce; the client should retry
+    /// with the nonce from the `DPoP-Nonce` response header.
+    UseNonce(String),
+    /// The nonce store failed; not the client's fault.
+    NonceStore(anyhow::Error),
+}
+
+impl DpopError {
+    /// The RFC 9449 error code. A failed nonce store has none of its own.
+    pub fn code(&self) -> &'static str {
+        match self {
+            DpopError::InvalidProof(_) => "invalid_dpop_proof",
+            DpopError::UseNonce(_) => "use_dpop_nonce",
+            DpopError::NonceStore(_) => "nonce_store_unavailable",
+        }
+    }
+}
+
+impl fmt::Display for DpopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DpopError::InvalidProof(msg) | DpopError::UseNonce(msg) => f.write_str(msg),
+            DpopError::NonceStore(_) => f.write_str("nonce store unavailable"),
+        }
+    }
+}
+
+/// The HTTP request a proof is expected to be bound to.
+pub struct DpopRequest<'a> {
+    pub method: &'a str,
+    pub url: &'a str,
+    /// Access token from an `Authorization: DPoP` header, if one was presented.
+    pub access_token: Option<&'a str>,
+}
+
+/// The key a DPoP proof is signed with. RFC 9449 requires it in the `jwk`
+/// header, so `kid`, `x5c`, `jku` and DID references are not resolved.
+pub fn proof_jwk(header: &Value) -> Result<&Map<String, Value>, DpopError> {
+    header
+        .get("jwk")
+        .and_then(Value::as_object)
+        .ok_or_else(|| DpopError::InvalidProof("jwk missing in header".to_string()))
+}
+
+/// Checks the DPoP-specific header and claims of a proof whose signature has
+/// already been verified. Time claims and the nonce are checked by the caller.
+pub fn check_proof(
+    header: &JwsHeader,
+    payload: &JwtPayload,
+    request: &DpopRequest<'_>,
+) -> Result<(), DpopError> {
+    if header.token_type() != Some(DPOP_TYPE) {
+        return Err(DpopError::InvalidProof(format!(
+            "typ must be {}",
+            DPOP_TYPE
+        )));
+    }
+
+    let htm = claim_str(payload, "htm")?;
+    if htm != request.method {
+        return Err(DpopError::InvalidProof(format!(
+            "htm {} does not match request method {}",
+            htm, request.method
+        )));
+    }
+
+    let htu = claim_str(payload, "htu")?;
+    if normalize_htu(htu) != normalize_htu(request.url) {
+        return Err(DpopError::InvalidProof(format!(
+            "htu {} does not match request URL {}",
+            htu, request.url
+        )));
+    }
+
+    claim_str(payload, "jti")?;
+
+    match (payload.claim("ath"), request.access_token) {
+        (Some(ath), Some(token)) => {
+            if ath.as_str() != Some(access_token_hash(token).as_str()) {
+                return Err(DpopError::InvalidProof(
+                    "ath does not match the access token".to_string(),
+                ));
+            }
+        }
+        (None, Some(_)) => {
+            return Err(DpopError::InvalidProof(
+                "ath is required when an access token is presented".to_string(),
+            ))
+        }
+        (Some(_), None) => {
+            return Err(DpopError::InvalidProof(
+                "ath given but no access token was presented".to_string(),
+            ))
+        }
+        (None, None) => {}
+    }
+
+    Ok(())
+}
+
+fn claim_str<'a>(payload: &'a JwtPayload, name: &str) -> Result<&'a str, DpopError> {
+    payload
+        .claim(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| DpopError::InvalidProof(format!("{} missing in claims", name)))
+}
+
+/// Strips the query and fragment, which RFC 9449 excludes from `htu`.
+fn normalize_htu(url: &str) -> &str {
+    let end = url.find(['?', '#']).unwrap_or(url.len());
+    &url[..end]
+}
+
+/// base64url SHA-256 of an access token, as carried in `ath`.
+pub fn access_token_hash(access_token: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(access_token.as_bytes()))
+}
+
+/// Remembers `jti`s of accepted proofs until they could no longer pass the
+/// `iat` freshness check.
+#[derive(Default)]
+pub struct JtiCache {
+    seen: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl JtiCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `jti` until `forget_at`. Returns false if it was already seen.
+    pub fn insert(&self, jti: &str, forget_at: SystemTime, now: SystemTime) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, t| now < *t);
+        if seen.contains_key(jti) {
+            return false;
+        }
+        seen.insert(jti.to_owned(), forget_at);
+        true
+    }
+}
+
+/// Mints a DPoP proof for an arbitrary HTTP request.
+pub fn mint_proof(
+    alg: ProofAlg,
+    private_key: &Jwk,
+    htm: &str,
+    htu: &str,
+    nonce: Option<&str>,
+    access_token: Option<&str>,
+) -> Result<String, JoseError> {
+    let mut header = JwsHeader::new();
+    header.set_token_type(DPOP_TYPE);
+    header.set_jwk(private_key.to_public_key()?);
+
+    let mut payload = JwtPayload::new();
+    payload.set_jwt_id(Uuid::new_v4().to_string());
+    payload.set_issued_at(&SystemTime::now());
+    payload.set_claim("htm", Some(json!(htm)))?;
+    payload.set_claim("htu", Some(json!(htu)))?;
+    if let Some(nonce) = nonce {
+        payload.set_claim("nonce", Some(json!(nonce)))?;
+    }
+    if let Some(token) = access_token {
+        payload.set_claim("ath", Some(json!(access_token_hash(token))))?;
+    }
+
+    let signer = alg.signer_from_jwk(private_key)?;
+    jwt::encode_with_signer(&payload, &header, &*signer)
+}
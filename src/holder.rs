@@ -0,0 +1,424 @@
+//! The holder side: minting ownership proofs and presenting them to a
+//! verifier over HTTP.
+
+use crate::algs::{KeyType, ProofAlg};
+use crate::{did, dpop, jws_json, thumbprint};
+use anyhow::{anyhow, bail, Context};
+use josekit::jwk::alg::{ec::EcKeyPair, ed::EdKeyPair, rsa::RsaKeyPair};
+use josekit::jwk::Jwk;
+use josekit::jws::JwsHeader;
+use josekit::jwt::{self, JwtPayload};
+use serde_json::{json, Map, Value};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Lifetime holders give their proofs via `exp`.
+pub const PROOF_LIFETIME: Duration = Duration::from_secs(60);
+
+/// How a holder's proof identifies its public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyReference {
+    /// Embed the public JWK in the `jwk` header.
+    Jwk,
+    /// Name the key with a `did:key` DID URL in the `kid` header.
+    DidKey,
+    /// Name the key with a `did:jwk` DID URL in the `kid` header.
+    DidJwk,
+}
+
+impl FromStr for KeyReference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jwk" => Ok(KeyReference::Jwk),
+            "did:key" => Ok(KeyReference::DidKey),
+            "did:jwk" => Ok(KeyReference::DidJwk),
+            _ => Err(format!(
+                "unknown key reference {:?}, expected jwk, did:key or did:jwk",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for KeyReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            KeyReference::Jwk => "jwk",
+            KeyReference::DidKey => "did:key",
+            KeyReference::DidJwk => "did:jwk",
+        })
+    }
+}
+
+/// Claims of an ownership proof other than its nonce.
+#[derive(Debug, Clone, Default)]
+pub struct ProofClaims {
+    /// `aud`, normally the verifier's URL.
+    pub audience: Option<String>,
+    /// `iss`, the holder's own identifier.
+    pub issuer: Option<String>,
+}
+
+/// The algorithm a holder signs with by default for `jwk`.
+pub fn default_alg(jwk: &Jwk) -> Option<ProofAlg> {
+    match (jwk.key_type(), jwk.curve()) {
+        ("EC", Some("P-256")) => Some(ProofAlg::ES256),
+        ("EC", Some("P-384")) => Some(ProofAlg::ES384),
+        ("EC", Some("P-521")) => Some(ProofAlg::ES512),
+        ("OKP", Some("Ed25519")) | ("OKP", Some("Ed448")) => Some(ProofAlg::EdDSA),
+        ("RSA", _) => Some(ProofAlg::RS256),
+        _ => None,
+    }
+}
+
+/// Reads a private key from a JWK or PKCS#8 PEM file.
+pub fn load_private_key(contents: &[u8]) -> anyhow::Result<Jwk> {
+    let is_json = contents.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{');
+    let jwk = if is_json {
+        Jwk::from_bytes(contents).context("parsing JWK")?
+    } else if let Ok(pair) = EcKeyPair::from_pem(contents, None) {
+        pair.to_jwk_private_key()
+    } else if let Ok(pair) = EdKeyPair::from_pem(contents) {
+        pair.to_jwk_private_key()
+    } else if let Ok(pair) = RsaKeyPair::from_pem(contents) {
+        pair.to_jwk_private_key()
+    } else {
+        bail!("not a JWK or a PEM-encoded EC, OKP or RSA private key");
+    };
+    if !jwk.as_ref().contains_key("d") {
+        bail!("the key has no private part");
+    }
+    Ok(jwk)
+}
+
+/// Encodes `private_key` as a PKCS#8 PEM private key.
+pub fn private_key_pem(private_key: &Jwk) -> anyhow::Result<Vec<u8>> {
+    Ok(match private_key.key_type() {
+        "EC" => EcKeyPair::from_jwk(private_key)?.to_pem_private_key(),
+        "OKP" => EdKeyPair::from_jwk(private_key)?.to_pem_private_key(),
+        "RSA" => RsaKeyPair::from_jwk(private_key)?.to_pem_private_key(),
+        kty => bail!("unsupported kty {:?}", kty),
+    })
+}
+
+/// Generates a key of `key_type`, tagged with its RFC 7638 thumbprint as `kid`.
+pub fn generate_key(key_type: KeyType) -> anyhow::Result<Jwk> {
+    let mut private_key = key_type.generate()?;
+    let kid =
+        thumbprint::sha256_thumbprint(&private_key.to_public_key()?).map_err(|e| anyhow!(e))?;
+    private_key.set_key_id(kid);
+    Ok(private_key)
+}
+
+fn proof_payload(nonce: &str, claims: &ProofClaims) -> anyhow::Result<JwtPayload> {
+    let now = SystemTime::now();
+    let mut payload = JwtPayload::new();
+    payload.set_claim("nonce", Some(json!(nonce)))?;
+    payload.set_issued_at(&now);
+    payload.set_not_before(&now);
+    payload.set_expires_at(&(now + PROOF_LIFETIME));
+    if let Some(audience) = &claims.audience {
+        payload.set_audience(vec![audience.as_str()]);
+    }
+    if let Some(issuer) = &claims.issuer {
+        payload.set_issuer(issuer);
+    }
+    Ok(payload)
+}
+
+/// Signs a compact ownership proof answering `nonce`. Needs no network
+/// access, so proofs can be minted on a machine that never talks to the
+/// verifier.
+pub fn sign_proof(
+    alg: ProofAlg,
+    private_key: &Jwk,
+    key_reference: KeyReference,
+    nonce: &str,
+    claims: &ProofClaims,
+) -> anyhow::Result<String> {
+    alg.check_key(private_key).map_err(|e| anyhow!(e))?;
+    // A signer writes its key's `kid` into the header, which would replace
+    // a DID URL, so DID-referenced proofs sign with the `kid` removed.
+    let private_key = match key_reference {
+        KeyReference::Jwk => private_key.clone(),
+        KeyReference::DidKey | KeyReference::DidJwk => {
+            let mut map: Map<String, Value> = private_key.as_ref().clone();
+            map.remove("kid");
+            Jwk::from_map(map)?
+        }
+    };
+    let public_key = private_key.to_public_key()?;
+
+    let mut header = JwsHeader::new();
+    header.set_token_type("JWT");
+    match key_reference {
+        KeyReference::Jwk => header.set_jwk(public_key),
+        KeyReference::DidKey => {
+            header.set_key_id(did::did_key_url(&public_key).map_err(|e| anyhow!(e))?)
+        }
+        KeyReference::DidJwk => {
+            header.set_key_id(did::did_jwk_url(&public_key).map_err(|e| anyhow!(e))?)
+        }
+    }
+
+    let payload = proof_payload(nonce, claims)?;
+    let signer = alg.signer_from_jwk(&private_key)?;
+    Ok(jwt::encode_with_signer(&payload, &header, &*signer)?)
+}
+
+/// What the verifier answered.
+#[derive(Debug)]
+pub struct VerifierResponse {
+    pub status: reqwest::StatusCode,
+    /// The response body, or `null` if it was not JSON.
+    pub body: Value,
+}
+
+impl VerifierResponse {
+    async fn read(response: reqwest::Response) -> anyhow::Result<Self> {
+        let status = response.status();
+        let bytes = response.bytes().await?;
+        Ok(VerifierResponse {
+            status,
+            body: serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        })
+    }
+}
+
+/// A holder talking to one verifier.
+pub struct Holder {
+    client: reqwest::Client,
+    verifier_url: String,
+    claims: ProofClaims,
+    admin_token: Option<String>,
+}
+
+impl Holder {
+    /// A holder for the verifier at `verifier_url`. Proofs name that URL as
+    /// their audience.
+    pub fn new(verifier_url: &str) -> Self {
+        let verifier_url = verifier_url.trim_end_matches('/').to_owned();
+        Holder {
+            client: reqwest::Client::new(),
+            claims: ProofClaims {
+                audience: Some(verifier_url.clone()),
+                issuer: None,
+            },
+            verifier_url,
+            admin_token: None,
+        }
+    }
+
+    /// Sets the `iss` of every proof.
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.claims.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Sets the registry admin token that enrollment is authorized with.
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.verifier_url, path)
+    }
+
+    /// Fetches a fresh nonce from `/nonce`, with optional query parameters.
+    pub async fn fetch_nonce(&self, query: &[(&str, String)]) -> anyhow::Result<String> {
+        let nonce_resp = self
+            .client
+            .get(self.url("/nonce"))
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        nonce_resp["nonce"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("nonce field missing"))
+    }
+
+    /// Enrolls the public part of `private_key` in the verifier's key
+    /// registry and returns its `kid`. Needs the admin token.
+    pub async fn enroll(&self, owner: &str, private_key: &Jwk) -> anyhow::Result<String> {
+        let token = self
+            .admin_token
+            .as_deref()
+            .ok_or_else(|| anyhow!("enrollment needs the registry admin token"))?;
+        let public_key = Value::Object(private_key.to_public_key()?.as_ref().clone());
+        let enrolled = self
+            .client
+            .post(self.url("/keys"))
+            .bearer_auth(token)
+            .json(&json!({ "owner": owner, "jwk": public_key }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+        enrolled["kid"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or_else(|| anyhow!("kid missing in enrollment response"))
+    }
+
+    /// Proves ownership of `private_key` with a compact proof.
+    pub async fn prove(
+        &self,
+        alg: ProofAlg,
+        private_key: &Jwk,
+        key_reference: KeyReference,
+    ) -> anyhow::Result<VerifierResponse> {
+        let nonce = self.fetch_nonce(&[]).await?;
+        let token = sign_proof(alg, private_key, key_reference, &nonce, &self.claims)?;
+        let response = self
+            .client
+            .post(self.url("/verify"))
+            .body(token)
+            .send()
+            .await?;
+        VerifierResponse::read(response).await
+    }
+
+    /// Proves ownership of several keys at once with a general JWS JSON
+    /// proof carrying one signature per key.
+    pub async fn prove_jws_json(
+        &self,
+        keys: &[(ProofAlg, Jwk)],
+    ) -> anyhow::Result<VerifierResponse> {
+        let nonce = self.fetch_nonce(&[]).await?;
+        let payload = proof_payload(&nonce, &self.claims)?;
+
+        let mut tokens = Vec::with_capacity(keys.len());
+        for (alg, private_key) in keys {
+            alg.check_key(private_key).map_err(|e| anyhow!(e))?;
+            let mut header = JwsHeader::new();
+            header.set_token_type("JWT");
+            header.set_jwk(private_key.to_public_key()?);
+            let signer = alg.signer_from_jwk(private_key)?;
+            tokens.push(jwt::encode_with_signer(&payload, &header, &*signer)?);
+        }
+        let body = jws_json::general_from_compact(&tokens).map_err(|e| anyhow!(e))?;
+
+        let response = self
+            .client
+            .post(self.url("/verify"))
+            .header(reqwest::header::CONTENT_TYPE, jws_json::CONTENT_TYPE)
+            .body(body.to_string())
+            .send()
+            .await?;
+        VerifierResponse::read(response).await
+    }
+
+    /// Asks for a challenge that `threshold` of the enrolled `keys` must
+    /// answer, and answers it with a bundle of compact proofs from the first
+    /// `threshold` of them, each naming its key by `kid`.
+    pub async fn prove_threshold(
+        &self,
+        keys: &[(ProofAlg, Jwk, String)],
+        threshold: usize,
+    ) -> anyhow::Result<VerifierResponse> {
+        let kids: Vec<&str> = keys.iter().map(|(_, _, kid)| kid.as_str()).collect();
+        let nonce = self
+            .fetch_nonce(&[
+                ("kids", kids.join(",")),
+                ("threshold", threshold.to_string()),
+            ])
+            .await?;
+        let payload = proof_payload(&nonce, &self.claims)?;
+
+        let mut tokens = Vec::with_capacity(threshold);
+        for (alg, private_key, kid) in keys.iter().take(threshold) {
+            let mut header = JwsHeader::new();
+            header.set_token_type("JWT");
+            header.set_key_id(kid);
+            let signer = alg.signer_from_jwk(private_key)?;
+            tokens.push(jwt::encode_with_signer(&payload, &header, &*signer)?);
+        }
+
+        let response = self
+            .client
+            .post(self.url("/verify"))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&tokens)?)
+            .send()
+            .await?;
+        VerifierResponse::read(response).await
+    }
+
+    /// Rotates the key enrolled under `kid` from `old` to `new` with a proof
+    /// signed by both keys.
+    pub async fn rotate(
+        &self,
+        kid: &str,
+        old: &(ProofAlg, Jwk),
+        new: &(ProofAlg, Jwk),
+    ) -> anyhow::Result<VerifierResponse> {
+        let nonce = self.fetch_nonce(&[]).await?;
+        let new_public = new.1.to_public_key()?;
+        let new_jkt = thumbprint::sha256_thumbprint(&new_public).map_err(|e| anyhow!(e))?;
+        let mut payload = proof_payload(&nonce, &self.claims)?;
+        payload.set_claim("rotation", Some(json!({ "kid": kid, "new_jkt": new_jkt })))?;
+
+        let mut old_header = JwsHeader::new();
+        old_header.set_token_type("JWT");
+        old_header.set_key_id(kid);
+        let mut new_header = JwsHeader::new();
+        new_header.set_token_type("JWT");
+        new_header.set_jwk(new_public);
+        let tokens = [
+            jwt::encode_with_signer(&payload, &old_header, &*old.0.signer_from_jwk(&old.1)?)?,
+            jwt::encode_with_signer(&payload, &new_header, &*new.0.signer_from_jwk(&new.1)?)?,
+        ];
+        let body = jws_json::general_from_compact(&tokens).map_err(|e| anyhow!(e))?;
+
+        let response = self
+            .client
+            .post(self.url("/keys/rotate"))
+            .header(reqwest::header::CONTENT_TYPE, jws_json::CONTENT_TYPE)
+            .body(body.to_string())
+            .send()
+            .await?;
+        VerifierResponse::read(response).await
+    }
+
+    /// Proves ownership with a DPoP proof bound to the `/verify` request,
+    /// picking up the verifier's nonce from the `use_dpop_nonce` challenge.
+    pub async fn prove_dpop(
+        &self,
+        alg: ProofAlg,
+        private_key: &Jwk,
+    ) -> anyhow::Result<VerifierResponse> {
+        let htu = self.url("/verify");
+
+        let proof = dpop::mint_proof(alg, private_key, "POST", &htu, None, None)?;
+        let challenge = self
+            .client
+            .post(&htu)
+            .header(dpop::DPOP_HEADER, proof)
+            .send()
+            .await?;
+        let nonce = challenge
+            .headers()
+            .get(dpop::DPOP_NONCE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| anyhow!("verifier did not send a DPoP-Nonce"))?
+            .to_owned();
+
+        let proof = dpop::mint_proof(alg, private_key, "POST", &htu, Some(&nonce), None)?;
+        let response = self
+            .client
+            .post(&htu)
+            .header(dpop::DPOP_HEADER, proof)
+            .send()
+            .await?;
+        VerifierResponse::read(response).await
+    }
+}
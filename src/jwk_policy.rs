@@ -0,0 +1,131 @@
+//! Policy for the public JWKs holders present in proofs or enroll.
+
+use crate::algs::ProofAlg;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Members that only appear in private or symmetric keys.
+const PRIVATE_MEMBERS: [&str; 8] = ["d", "p", "q", "dp", "dq", "qi", "oth", "k"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JwkPolicyError {
+    /// The JWK carries private or symmetric key material.
+    PrivateMember(String),
+    /// `use` is present but is not `sig`.
+    InvalidUse(String),
+    /// `key_ops` is present but does not allow `verify`.
+    InvalidKeyOps,
+    /// The JWK's own `alg` disagrees with the JWS `alg`.
+    AlgMismatch { jwk_alg: String, jws_alg: ProofAlg },
+    /// A required member is missing or has the wrong shape for the key type.
+    InvalidParameter(String),
+}
+
+impl JwkPolicyError {
+    /// Stable machine-readable code for the rejection.
+    pub fn code(&self) -> &'static str {
+        match self {
+            JwkPolicyError::PrivateMember(_) => "jwk_private_key",
+            JwkPolicyError::InvalidUse(_) => "jwk_invalid_use",
+            JwkPolicyError::InvalidKeyOps => "jwk_invalid_key_ops",
+            JwkPolicyError::AlgMismatch { .. } => "jwk_alg_mismatch",
+            JwkPolicyError::InvalidParameter(_) => "jwk_invalid_params",
+        }
+    }
+}
+
+impl fmt::Display for JwkPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JwkPolicyError::PrivateMember(name) => {
+                write!(f, "JWK must not contain private member {:?}", name)
+            }
+            JwkPolicyError::InvalidUse(value) => {
+                write!(f, "JWK use must be \"sig\", got {:?}", value)
+            }
+            JwkPolicyError::InvalidKeyOps => write!(f, "JWK key_ops must include \"verify\""),
+            JwkPolicyError::AlgMismatch { jwk_alg, jws_alg } => {
+                write!(f, "JWK alg {:?} does not match JWS alg {}", jwk_alg, jws_alg)
+            }
+            JwkPolicyError::InvalidParameter(msg) => write!(f, "invalid JWK: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for JwkPolicyError {}
+
+/// Checks that `jwk` is a well-formed public signature key usable with `jws_alg`.
+pub fn check_public_jwk(jwk: &Map<String, Value>, jws_alg: ProofAlg) -> Result<(), JwkPolicyError> {
+    check_public_key(jwk)?;
+
+    match jwk.get("alg") {
+        None => {}
+        Some(Value::String(value)) if value == jws_alg.name() => {}
+        Some(value) => {
+            return Err(JwkPolicyError::AlgMismatch {
+                jwk_alg: value.as_str().map(str::to_owned).unwrap_or_else(|| value.to_string()),
+                jws_alg,
+            })
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that `jwk` is a well-formed public signature key, whatever
+/// algorithm it will later be used with.
+pub fn check_public_key(jwk: &Map<String, Value>) -> Result<(), JwkPolicyError> {
+    if let Some(name) = PRIVATE_MEMBERS.iter().find(|name| jwk.contains_key(**name)) {
+        return Err(JwkPolicyError::PrivateMember(name.to_string()));
+    }
+
+    match jwk.get("use") {
+        None => {}
+        Some(Value::String(value)) if value == "sig" => {}
+        Some(value) => return Err(JwkPolicyError::InvalidUse(value.to_string())),
+    }
+
+    if let Some(key_ops) = jwk.get("key_ops") {
+        let allows_verify = key_ops
+            .as_array()
+            .map(|ops| ops.iter().any(|op| op.as_str() == Some("verify")))
+            .unwrap_or(false);
+        if !allows_verify {
+            return Err(JwkPolicyError::InvalidKeyOps);
+        }
+    }
+
+    let required: &[&str] = match jwk.get("kty").and_then(Value::as_str) {
+        Some("EC") => &["crv", "x", "y"],
+        Some("OKP") => &["crv", "x"],
+        Some("RSA") => &["n", "e"],
+        Some(kty) => {
+            return Err(JwkPolicyError::InvalidParameter(format!(
+                "unsupported kty {:?}",
+                kty
+            )))
+        }
+        None => return Err(JwkPolicyError::InvalidParameter("kty is missing".to_string())),
+    };
+    for name in required {
+        match jwk.get(*name) {
+            Some(Value::String(value)) if !value.is_empty() => {}
+            Some(_) => {
+                return Err(JwkPolicyError::InvalidParameter(format!(
+                    "{} must be a non-empty string",
+                    name
+                )))
+            }
+            None => {
+                return Err(JwkPolicyError::InvalidParameter(format!("{} is missing", name)))
+            }
+        }
+    }
+    if jwk.get("kty").and_then(Value::as_str) == Some("OKP") && jwk.contains_key("y") {
+        return Err(JwkPolicyError::InvalidParameter(
+            "OKP keys have no y coordinate".to_string(),
+        ));
+    }
+
+    Ok(())
+}
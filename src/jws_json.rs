@@ -0,0 +1,151 @@
+//! JWS JSON Serialization (RFC 7515 section 7.2), flattened and general.
+
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Map, Value};
+
+pub const CONTENT_TYPE: &str = "application/jose+json";
+/// Most signatures accepted in one JWS JSON object or bundle. Each may cost
+/// a remote key fetch or an RSA verification.
+pub const MAX_SIGNATURES: usize = 10;
+
+/// One signature of a JWS JSON object or bundle, as a compact JWS so it can
+/// go through the same verification as a compact proof.
+pub struct Signature {
+    pub token: String,
+    /// The JOSE Header: the protected header merged with the unprotected one.
+    pub header: Value,
+}
+
+/// Splits a flattened or general JWS JSON object into its signatures.
+pub fn parse(body: &str) -> Result<Vec<Signature>, String> {
+    let value: Value =
+        serde_json::from_str(body).map_err(|e| format!("invalid JWS JSON: {}", e))?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| "JWS JSON must be an object".to_string())?;
+    let payload = object
+        .get("payload")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "JWS JSON payload must be a string".to_string())?;
+
+    match object.get("signatures") {
+        Some(signatures) => {
+            if ["protected", "header", "signature"]
+                .iter()
+                .any(|name| object.contains_key(*name))
+            {
+                return Err("general JWS JSON must not mix in flattened members".to_string());
+            }
+            let signatures = signatures
+                .as_array()
+                .filter(|signatures| !signatures.is_empty())
+                .ok_or_else(|| "signatures must be a non-empty array".to_string())?;
+            check_count(signatures.len())?;
+            signatures
+                .iter()
+                .map(|signature| {
+                    let signature = signature
+                        .as_object()
+                        .ok_or_else(|| "each signature must be an object".to_string())?;
+                    parse_signature(payload, signature)
+                })
+                .collect()
+        }
+        None => Ok(vec![parse_signature(payload, object)?]),
+    }
+}
+
+fn parse_signature(payload: &str, object: &Map<String, Value>) -> Result<Signature, String> {
+    let protected = object
+        .get("protected")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "protected header missing".to_string())?;
+    let signature = object
+        .get("signature")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "signature missing".to_string())?;
+
+    let protected_bytes = general_purpose::URL_SAFE_NO_PAD
+        .decode(protected)
+        .map_err(|e| format!("protected header base64 decode error: {}", e))?;
+    let mut header: Map<String, Value> = serde_json::from_slice(&protected_bytes)
+        .map_err(|e| format!("protected header is not a JSON object: {}", e))?;
+    // The signature only covers the protected header, so the algorithm must be there.
+    if !header.contains_key("alg") {
+        return Err("alg must be in the protected header".to_string());
+    }
+    match object.get("header") {
+        None => {}
+        Some(Value::Object(unprotected)) => {
+            for (name, value) in unprotected {
+                if header.contains_key(name) {
+                    return Err(format!(
+                        "header parameter {:?} is both protected and unprotected",
+                        name
+                    ));
+                }
+                header.insert(name.clone(), value.clone());
+            }
+        }
+        Some(_) => return Err("unprotected header must be a JSON object".to_string()),
+    }
+
+    Ok(Signature {
+        token: format!("{}.{}.{}", protected, payload, signature),
+        header: Value::Object(header),
+    })
+}
+
+/// Parses a bundle of compact JWSs, a JSON array of strings, each signed
+/// with its own key and carrying its own payload.
+pub fn parse_bundle(body: &str) -> Result<Vec<Signature>, String> {
+    let tokens: Vec<String> =
+        serde_json::from_str(body).map_err(|e| format!("invalid JWS bundle: {}", e))?;
+    if tokens.is_empty() {
+        return Err("JWS bundle must not be empty".to_string());
+    }
+    check_count(tokens.len())?;
+    tokens
+        .into_iter()
+        .map(|token| {
+            let protected = token.split('.').next().unwrap_or_default();
+            let header_bytes = general_purpose::URL_SAFE_NO_PAD
+                .decode(protected)
+                .map_err(|e| format!("header base64 decode error: {}", e))?;
+            let header = serde_json::from_slice(&header_bytes)
+                .map_err(|e| format!("header JSON decode error: {}", e))?;
+            Ok(Signature { token, header })
+        })
+        .collect()
+}
+
+fn check_count(count: usize) -> Result<(), String> {
+    if count > MAX_SIGNATURES {
+        return Err(format!(
+            "at most {} signatures are accepted, got {}",
+            MAX_SIGNATURES, count
+        ));
+    }
+    Ok(())
+}
+
+/// Combines compact JWSs over the same payload into one general JWS JSON
+/// object.
+pub fn general_from_compact(tokens: &[String]) -> Result<Value, String> {
+    let mut payload = None;
+    let mut signatures = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let parts: Vec<&str> = token.split('.').collect();
+        if parts.len() != 3 {
+            return Err("JWS must have 3 parts".to_string());
+        }
+        match payload {
+            None => payload = Some(parts[1]),
+            Some(existing) if existing == parts[1] => {}
+            Some(_) => return Err("JWSs sign different payloads".to_string()),
+        }
+        signatures.push(json!({ "protected": parts[0], "signature": parts[2] }));
+    }
+    let payload = payload.ok_or_else(|| "no JWS to combine".to_string())?;
+    Ok(json!({ "payload": payload, "signatures": signatures }))
+}
@@ -0,0 +1,128 @@
+//! The verifier's own signing keys.
+
+use crate::algs::ProofAlg;
+use anyhow::{anyhow, Context};
+use josekit::{jwk::Jwk, JoseError};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// A private key the verifier signs with.
+#[derive(Clone)]
+pub struct SigningKey {
+    pub kid: String,
+    pub alg: ProofAlg,
+    pub jwk: Jwk,
+}
+
+/// Signing keys loaded from a JWK Set file.
+///
+/// The first key in the file signs new artifacts; the rest are kept so that
+/// artifacts signed before a rotation stay verifiable. Rotating is a matter
+/// of publishing the new key behind the active one, moving it to the front
+/// once relying parties have refreshed their copy of the JWK Set, and
+/// dropping the retired key after its artifacts have expired.
+/// [`KeyRing::reload_if_changed`] picks up edits without a restart.
+pub struct KeyRing {
+    path: PathBuf,
+    state: RwLock<KeyRingState>,
+}
+
+struct KeyRingState {
+    keys: Arc<Vec<SigningKey>>,
+    modified: Option<SystemTime>,
+}
+
+impl KeyRing {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let modified = fs::metadata(&path)?.modified().ok();
+        let keys = read_keys(&path)?;
+        Ok(KeyRing {
+            path,
+            state: RwLock::new(KeyRingState {
+                keys: Arc::new(keys),
+                modified,
+            }),
+        })
+    }
+
+    /// The key that signs new artifacts.
+    pub fn active(&self) -> SigningKey {
+        self.state.read().unwrap().keys[0].clone()
+    }
+
+    /// Every key currently in the ring, active key first.
+    pub fn keys(&self) -> Arc<Vec<SigningKey>> {
+        self.state.read().unwrap().keys.clone()
+    }
+
+    /// Public halves of every key in the ring, for publication in a JWK Set.
+    pub fn public_jwks(&self) -> Result<Vec<Jwk>, JoseError> {
+        self.keys()
+            .iter()
+            .map(|key| {
+                let mut jwk = key.jwk.to_public_key()?;
+                jwk.set_key_id(&key.kid);
+                jwk.set_algorithm(key.alg.name());
+                jwk.set_key_use("sig");
+                Ok(jwk)
+            })
+            .collect()
+    }
+
+    /// Re-reads the key file if it changed since the last load. A file that
+    /// fails to parse leaves the current keys in place.
+    pub fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        if modified == self.state.read().unwrap().modified {
+            return Ok(false);
+        }
+        let keys = read_keys(&self.path)?;
+        let mut state = self.state.write().unwrap();
+        state.keys = Arc::new(keys);
+        state.modified = modified;
+        Ok(true)
+    }
+}
+
+fn read_keys(path: &Path) -> anyhow::Result<Vec<SigningKey>> {
+    let contents = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    let set: Value = serde_json::from_slice(&contents)
+        .with_context(|| format!("parsing {}", path.display()))?;
+    let entries = set
+        .get("keys")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("{} is not a JWK Set", path.display()))?;
+
+    let mut keys = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let map = entry
+            .as_object()
+            .ok_or_else(|| anyhow!("JWK Set entries must be objects"))?;
+        let jwk = Jwk::from_map(map.clone())?;
+        let kid = jwk
+            .key_id()
+            .ok_or_else(|| anyhow!("signing keys need a kid"))?
+            .to_owned();
+        let alg: ProofAlg = jwk
+            .algorithm()
+            .ok_or_else(|| anyhow!("signing key {} needs an alg", kid))?
+            .parse()
+            .map_err(|e: String| anyhow!("signing key {}: {}", kid, e))?;
+        alg.check_key(&jwk)
+            .map_err(|e| anyhow!("signing key {}: {}", kid, e))?;
+        alg.signer_from_jwk(&jwk)
+            .with_context(|| format!("signing key {} is not a usable private key", kid))?;
+        if keys.iter().any(|k: &SigningKey| k.kid == kid) {
+            return Err(anyhow!("duplicate kid {}", kid));
+        }
+        keys.push(SigningKey { kid, alg, jwk });
+    }
+    if keys.is_empty() {
+        return Err(anyhow!("{} contains no keys", path.display()));
+    }
+    Ok(keys)
+}
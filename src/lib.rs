@@ -0,0 +1,30 @@
+//! Verification of key ownership proofs, independent of any HTTP framework.
+//!
+//! [`Verifier`] resolves a proof's key, checks its signature and claims
+//! against a [`Policy`], and consumes its nonce through a [`NonceStore`].
+//! The `kop-verifier` binary is an actix-web service over it, and
+//! the `kop` binary is a holder CLI over [`holder`].
+
+pub mod algs;
+pub mod claims;
+pub mod did;
+pub mod dpop;
+pub mod holder;
+pub mod jwk_policy;
+pub mod jws_json;
+pub mod keyring;
+pub mod openid4vci;
+pub mod receipt;
+pub mod registry;
+pub mod remote_keys;
+pub mod sd_jwt;
+pub mod store;
+pub mod threshold;
+pub mod thumbprint;
+pub mod verifier;
+pub mod x5c;
+
+pub use store::NonceStore;
+pub use verifier::{
+    decode_jwt_header, MultiProof, MultiProofError, Policy, ProofError, VerifiedProof, Verifier,
+};
@@ -1,167 +1,1187 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use base64::{engine::general_purpose, Engine as _};
-use josekit::{
-    jwk::{self, Jwk},
-    jws::{JwsHeader, ES256},
-    jwt,
-    JoseError,
+use actix_web::error::InternalError;
+use actix_web::http::{header, StatusCode};
+use actix_web::{
+    middleware, web, App, HttpRequest, HttpResponse, HttpServer, Responder, ResponseError,
 };
-use serde_json::{json, Value};
-use std::collections::HashSet;
-use std::sync::Mutex;
-use uuid::Uuid;
-use reqwest;
-use anyhow::anyhow;
+use anyhow::Context;
+use clap::Parser;
+use josekit::jwk::Jwk;
+use josekit::JoseError;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+mod config;
+mod tls;
+
+use config::{ClientAuth, Config};
+use key_ownership_prover::dpop::{self, DpopError, JtiCache, DPOP_HEADER, DPOP_NONCE_HEADER};
+use key_ownership_prover::keyring::KeyRing;
+use key_ownership_prover::openid4vci::{self, Oid4vciError, Oid4vciPolicy};
+use key_ownership_prover::receipt::ReceiptIssuer;
+use key_ownership_prover::registry::{KeyRegistry, RegistryError, RotationError};
+use key_ownership_prover::remote_keys::{FetchError, RemoteKeys};
+use key_ownership_prover::sd_jwt::{IssuerKeys, PresentationError};
+use key_ownership_prover::threshold::ThresholdChallenge;
+use key_ownership_prover::x5c::TrustAnchors;
+use key_ownership_prover::{did, jwk_policy, jws_json, store, thumbprint};
+use key_ownership_prover::{
+    MultiProof, MultiProofError, NonceStore, Policy, ProofError, VerifiedProof, Verifier,
+};
+use tls::ClientCertificate;
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+/// Prefix of the problem `type` URIs; the suffix is the error code.
+const PROBLEM_TYPE_PREFIX: &str = "urn:kop:problem:";
+const KEY_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+/// How long relying parties may cache the JWK Set. Keep new keys published
+/// for at least this long before they start signing.
+const JWKS_MAX_AGE_SECS: u64 = 300;
 
 struct AppState {
-    nonces: Mutex<HashSet<String>>,
+    verifier: Verifier,
+    policy: Policy,
+    nonce_ttl: Duration,
+    receipts: Option<ReceiptIssuer>,
+    /// Set when DPoP proofs are accepted.
+    dpop: Option<DpopPolicy>,
+    /// Set when `openid4vci-proof+jwt` proofs are accepted.
+    oid4vci: Option<Oid4vciPolicy>,
+    /// Trusted SD-JWT issuer keys; SD-JWT presentations are refused without them.
+    sd_jwt_issuers: Option<IssuerKeys>,
+    /// Bearer token required to enroll and look up keys. Both are disabled
+    /// without one.
+    registry_admin_token: Option<String>,
+    /// Proofs must be signed with the TLS client certificate's key.
+    require_proof_key_match: bool,
+}
+
+struct DpopPolicy {
+    /// The `htu` proofs must carry: the configured external URL of `/verify`,
+    /// never one derived from client-supplied `Host` or `Forwarded` headers.
+    htu: String,
+    jtis: JtiCache,
+}
+
+#[derive(Deserialize)]
+struct NonceQuery {
+    /// Comma-separated `kid`s of registered keys that may answer the nonce.
+    kids: Option<String>,
+    /// How many of `kids` must answer.
+    threshold: Option<usize>,
+}
+
+async fn generate_nonce(
+    data: web::Data<AppState>,
+    query: web::Query<NonceQuery>,
+) -> impl Responder {
+    let challenge = match (&query.kids, query.threshold) {
+        (None, None) => None,
+        (Some(kids), Some(threshold)) => {
+            let kids = kids.split(',').map(|kid| kid.trim().to_owned()).collect();
+            let challenge = match ThresholdChallenge::new(kids, threshold) {
+                Ok(challenge) => challenge,
+                Err(e) => return invalid_challenge(&e),
+            };
+            if let Some(kid) = challenge
+                .kids
+                .iter()
+                .find(|kid| data.verifier.registry.get(kid).is_none())
+            {
+                return invalid_challenge(&format!("unknown kid {:?}", kid));
+            }
+            Some(challenge)
+        }
+        _ => return invalid_challenge("kids and threshold must be given together"),
+    };
+
+    let expires_at = SystemTime::now() + data.nonce_ttl;
+    let challenge_json = challenge.as_ref().map(ThresholdChallenge::to_json);
+    let nonce = match data.verifier.issue_nonce(expires_at, challenge).await {
+        Ok(nonce) => nonce,
+        Err(e) => return proof_error(ProofError::NonceStore(e)),
+    };
+    let mut response = json!({
+        "nonce": nonce,
+        "expires_in": data.nonce_ttl.as_secs(),
+    });
+    if let Some(challenge) = challenge_json {
+        response["challenge"] = challenge;
+    }
+    HttpResponse::Ok().json(response)
+}
+
+fn invalid_challenge(detail: &str) -> HttpResponse {
+    request_error(
+        StatusCode::BAD_REQUEST,
+        "invalid_challenge",
+        "Invalid threshold challenge",
+        detail,
+    )
+}
+
+/// Publishes the public keys that sign receipts. Stateless nonces are MACed
+/// with a shared secret and have nothing to publish.
+async fn jwks(data: web::Data<AppState>) -> impl Responder {
+    let keys = match &data.receipts {
+        Some(receipts) => match receipts.keys.public_jwks() {
+            Ok(keys) => keys,
+            Err(e) => {
+                log::error!("failed to export receipt keys: {}", e);
+                return request_error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "signing_keys_unavailable",
+                    "Signing keys unavailable",
+                    "the receipt signing keys cannot be published",
+                );
+            }
+        },
+        None => Vec::new(),
+    };
+    let body = json!({ "keys": keys.iter().map(|k| k.as_ref()).collect::<Vec<_>>() });
+    HttpResponse::Ok()
+        .insert_header((
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", JWKS_MAX_AGE_SECS),
+        ))
+        .content_type("application/jwk-set+json")
+        .body(body.to_string())
+}
+
+/// HTTP status for a rejected proof: 400 when the proof cannot be read,
+/// 422 when it is readable but unacceptable, 401 when it does not
+/// authenticate the holder, 409 when the nonce cannot be used this way, 429
+/// when a remote key host's fetch allowance is used up, and 5xx when the
+/// failure is on our side or a remote key host's.
+fn proof_status(e: &ProofError) -> StatusCode {
+    match e {
+        ProofError::Malformed(_) => StatusCode::BAD_REQUEST,
+        ProofError::UnsupportedAlg(_)
+        | ProofError::InvalidKey(_)
+        | ProofError::KeyPolicy(_)
+        | ProofError::Certificate(_)
+        | ProofError::InvalidClaims(_)
+        | ProofError::NonceMissing
+        | ProofError::NonceMismatch => StatusCode::UNPROCESSABLE_ENTITY,
+        ProofError::RemoteKey(
+            FetchError::InvalidUrl(_) | FetchError::HostNotAllowed(_) | FetchError::KeyNotFound(_),
+        ) => StatusCode::UNPROCESSABLE_ENTITY,
+        ProofError::RemoteKey(FetchError::RateLimited(_)) => StatusCode::TOO_MANY_REQUESTS,
+        ProofError::RemoteKey(_) => StatusCode::BAD_GATEWAY,
+        ProofError::BadSignature(_)
+        | ProofError::BoundKeyMismatch
+        | ProofError::NonceExpired
+        | ProofError::NonceUnknown
+        | ProofError::ThresholdNotMet { .. }
+        | ProofError::NoSignatureVerified => StatusCode::UNAUTHORIZED,
+        ProofError::NonceReused | ProofError::ThresholdRequired => StatusCode::CONFLICT,
+        ProofError::NonceStore(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+/// Renders a rejected proof as an RFC 9457 problem document, with `extra`
+/// members added alongside the standard ones.
+fn problem(e: &ProofError, extra: Map<String, Value>) -> HttpResponse {
+    if let ProofError::NonceStore(inner) = e {
+        log::error!("nonce store failed: {}", inner);
+    }
+    problem_response(proof_status(e), e.code(), e.title(), &e.to_string(), extra)
+}
+
+fn problem_response(
+    status: StatusCode,
+    code: &str,
+    title: &str,
+    detail: &str,
+    extra: Map<String, Value>,
+) -> HttpResponse {
+    let mut body = json!({
+        "type": format!("{}{}", PROBLEM_TYPE_PREFIX, code),
+        "title": title,
+        "status": status.as_u16(),
+        "detail": detail,
+        "code": code,
+    });
+    body.as_object_mut().unwrap().extend(extra);
+    HttpResponse::build(status)
+        .content_type(PROBLEM_CONTENT_TYPE)
+        .body(body.to_string())
+}
+
+fn proof_error(e: ProofError) -> HttpResponse {
+    problem(&e, Map::new())
+}
+
+/// Renders a failure that is not about a proof, such as a malformed
+/// enrollment or a disabled feature.
+fn request_error(status: StatusCode, code: &str, title: &str, detail: &str) -> HttpResponse {
+    problem_response(status, code, title, detail, Map::new())
+}
+
+/// Renders a request the extractors refused: a body that is too large, not
+/// UTF-8 or not the expected JSON, or a malformed query string.
+fn malformed_request(e: &dyn ResponseError) -> HttpResponse {
+    request_error(
+        e.status_code(),
+        "malformed_request",
+        "Malformed request",
+        &e.to_string(),
+    )
 }
 
-fn decode_jwt_header(token: &str) -> Result<Value, JoseError> {
-    let parts: Vec<&str> = token.split('.').collect();
-    if parts.len() != 3 {
-        return Err(JoseError::InvalidJwtFormat(anyhow!("JWT must have 3 parts")));
+/// Why a request cannot meet `require_proof_key_match`.
+enum ClientCertError {
+    Missing,
+    Unusable(String),
+}
+
+fn client_certificate_error(e: ClientCertError) -> HttpResponse {
+    match e {
+        ClientCertError::Missing => request_error(
+            StatusCode::UNAUTHORIZED,
+            "client_certificate_required",
+            "Client certificate required",
+            "proofs must arrive over TLS with a client certificate",
+        ),
+        ClientCertError::Unusable(e) => request_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "client_certificate_unusable",
+            "Unusable client certificate",
+            &format!("the client certificate's key cannot be matched: {}", e),
+        ),
     }
-    let header_bytes = general_purpose::URL_SAFE_NO_PAD
-        .decode(parts[0])
-        .map_err(|e| JoseError::InvalidJwtFormat(anyhow!("Base64 decode error: {}", e)))?;
-    let header_value: Value = serde_json::from_slice(&header_bytes)
-        .map_err(|e| JoseError::InvalidJwtFormat(anyhow!("JSON decode error: {}", e)))?;
-    Ok(header_value)
 }
 
-async fn generate_nonce(data: web::Data<AppState>) -> impl Responder {
-    let nonce = Uuid::new_v4().to_string();
-    data.nonces.lock().unwrap().insert(nonce.clone());
-    HttpResponse::Ok().json(json!({ "nonce": nonce }))
+/// The policy for one proof request. With `require_proof_key_match`, the
+/// proof must be signed with the key of the request's TLS client certificate.
+fn request_policy<'a>(
+    req: &HttpRequest,
+    data: &'a AppState,
+) -> Result<Cow<'a, Policy>, ClientCertError> {
+    if !data.require_proof_key_match {
+        return Ok(Cow::Borrowed(&data.policy));
+    }
+    let jkt = match req.conn_data::<ClientCertificate>() {
+        Some(ClientCertificate { jkt: Ok(jkt) }) => jkt.clone(),
+        Some(ClientCertificate { jkt: Err(e) }) => {
+            return Err(ClientCertError::Unusable(e.clone()))
+        }
+        None => return Err(ClientCertError::Missing),
+    };
+    Ok(Cow::Owned(Policy {
+        bound_jkt: Some(jkt),
+        ..data.policy.clone()
+    }))
 }
 
 async fn verify_attestation(
+    req: HttpRequest,
     data: web::Data<AppState>,
-    body: String,
+    body: Result<String, actix_web::Error>,
 ) -> impl Responder {
-    let token = body.trim();
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => return malformed_request(e.as_response_error()),
+    };
+    let proof_policy = match request_policy(&req, &data) {
+        Ok(policy) => policy,
+        Err(e) => return client_certificate_error(e),
+    };
+    if let Some(dpop_proof) = req.headers().get(DPOP_HEADER) {
+        return match (&data.dpop, dpop_proof.to_str()) {
+            (Some(policy), Ok(token)) => {
+                verify_dpop(&req, &data, policy, &proof_policy, token).await
+            }
+            (Some(_), Err(_)) => {
+                dpop_error(
+                    &data,
+                    DpopError::InvalidProof("DPoP header is not valid ASCII".to_string()),
+                )
+                .await
+            }
+            (None, _) => request_error(
+                StatusCode::BAD_REQUEST,
+                "dpop_not_enabled",
+                "DPoP not enabled",
+                "this verifier does not accept DPoP proofs",
+            ),
+        };
+    }
 
-    let header_value = match decode_jwt_header(token) {
-        Ok(val) => val,
-        Err(e) => {
-            return HttpResponse::BadRequest().json(json!({ "error": e.to_string() }))
+    let mime = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|mime| mime.trim().to_ascii_lowercase());
+    // A plain JSON body is told apart by its shape, so that a compact proof
+    // still verifies when sent as application/json.
+    let signatures = match (mime.as_deref(), body.trim_start().as_bytes().first()) {
+        (Some(jws_json::CONTENT_TYPE), _) | (Some("application/json"), Some(b'{')) => {
+            Some(jws_json::parse(&body))
         }
+        (Some("application/json"), Some(b'[')) => Some(jws_json::parse_bundle(&body)),
+        _ => None,
     };
+    if let Some(signatures) = signatures {
+        return match signatures {
+            Ok(signatures) => verify_signatures(&data, &proof_policy, &signatures).await,
+            Err(e) => proof_error(ProofError::Malformed(e)),
+        };
+    }
 
-    let jwk_value = match header_value.get("jwk") {
-        Some(v) => v.clone(),
-        None => {
-            return HttpResponse::BadRequest()
-                .json(json!({ "error": "JWK missing in header" }))
+    let token = body.trim();
+    if let Some(policy) = &data.oid4vci {
+        let typ = key_ownership_prover::decode_jwt_header(token)
+            .ok()
+            .and_then(|h| h.get("typ").and_then(Value::as_str).map(str::to_owned));
+        if typ.as_deref() == Some(openid4vci::PROOF_TYPE) {
+            return verify_openid4vci(&data, policy, &proof_policy, token).await;
         }
+    }
+
+    let proof = match data.verifier.verify(token, &proof_policy).await {
+        Ok(proof) => proof,
+        Err(e) => return proof_error(e),
     };
+    match success_response(&data, &proof, proof.nonce().unwrap_or_default()) {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => receipt_error(e),
+    }
+}
 
-    let jwk = match jwk_value.as_object() {
-        Some(map) => match Jwk::from_map(map.clone()) {
-            Ok(jwk) => jwk,
-            Err(e) => {
-                return HttpResponse::BadRequest()
-                    .json(json!({ "error": format!("failed to parse JWK: {}", e) }))
+/// Verifies a JWS in JSON Serialization or a bundle of compact JWSs, and
+/// reports each signature.
+async fn verify_signatures(
+    data: &AppState,
+    policy: &Policy,
+    signatures: &[jws_json::Signature],
+) -> HttpResponse {
+    let proof = match data.verifier.verify_all(signatures, policy).await {
+        Ok(proof) => proof,
+        Err(MultiProofError { error, proof }) => {
+            let mut extra = Map::new();
+            if let ProofError::NoSignatureVerified | ProofError::ThresholdNotMet { .. } = &error {
+                extra.insert(
+                    "signatures".to_string(),
+                    json!(signature_report(&proof, Vec::new())),
+                );
             }
-        },
-        None => {
-            return HttpResponse::BadRequest()
-                .json(json!({ "error": "JWK is not a JSON object" }))
+            if let Some(challenge) = &proof.challenge {
+                extra.insert("challenge".to_string(), challenge.to_json());
+            }
+            return problem(&error, extra);
         }
     };
 
-    let verifier = match ES256.verifier_from_jwk(&jwk) {
-        Ok(v) => v,
-        Err(e) => {
-            return HttpResponse::BadRequest()
-                .json(json!({ "error": e.to_string() }))
+    let first = proof.verified().next();
+    let nonce = first.and_then(VerifiedProof::nonce).unwrap_or_default();
+    let mut receipts = Vec::new();
+    for verified in proof.verified() {
+        match issue_receipt(data, verified, nonce) {
+            Ok(receipt) => receipts.push(receipt),
+            Err(e) => return receipt_error(e),
+        }
+    }
+    let mut response = json!({
+        "status": "success",
+        "claims": first.map(|p| p.payload.claims_set()),
+        "signatures": signature_report(&proof, receipts),
+    });
+    if let Some(challenge) = &proof.challenge {
+        response["challenge"] = challenge.to_json();
+    }
+    HttpResponse::Ok().json(response)
+}
+
+/// Reports every signature in order. `receipts` holds one entry per verified
+/// signature, and is empty when none were issued.
+fn signature_report(proof: &MultiProof, receipts: Vec<Option<String>>) -> Vec<Value> {
+    let mut receipts = receipts.into_iter();
+    proof
+        .signatures
+        .iter()
+        .enumerate()
+        .map(|(index, result)| {
+            let mut entry = match result {
+                Ok(verified) => {
+                    let mut entry = key_summary(verified);
+                    entry["verified"] = json!(true);
+                    if let Some(Some(receipt)) = receipts.next() {
+                        entry["receipt"] = json!(receipt);
+                    }
+                    entry
+                }
+                Err(e) => json!({
+                    "verified": false,
+                    "code": e.code(),
+                    "detail": e.to_string(),
+                }),
+            };
+            entry["index"] = json!(index);
+            if let Some(counted) = &proof.counted {
+                entry["counted"] = json!(counted[index]);
+            }
+            entry
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct EnrollRequest {
+    /// Defaults to the key's own `kid`, then to its RFC 7638 thumbprint.
+    kid: Option<String>,
+    owner: String,
+    jwk: Map<String, Value>,
+}
+
+/// Enrolls a public key under a `kid` so proofs can name it instead of
+/// embedding it.
+async fn enroll_key(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    body: web::Json<EnrollRequest>,
+) -> impl Responder {
+    if let Some(denied) = admin_token_error(&req, &data) {
+        return denied;
+    }
+
+    let EnrollRequest { kid, owner, jwk } = body.into_inner();
+    if owner.is_empty() {
+        return request_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_enrollment",
+            "Invalid enrollment",
+            "owner must not be empty",
+        );
+    }
+    if let Err(e) = jwk_policy::check_public_key(&jwk) {
+        return proof_error(ProofError::KeyPolicy(e));
+    }
+    let parsed = match Jwk::from_map(jwk.clone()) {
+        Ok(parsed) => parsed,
+        Err(e) => return proof_error(ProofError::InvalidKey(e.to_string())),
+    };
+    let thumbprint = match thumbprint::sha256_thumbprint(&parsed) {
+        Ok(t) => t,
+        Err(e) => return proof_error(ProofError::InvalidKey(e)),
+    };
+    let kid = kid
+        .or_else(|| parsed.key_id().map(str::to_owned))
+        .unwrap_or_else(|| thumbprint.clone());
+    if let Err(reason) = check_kid(&kid) {
+        return request_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_enrollment",
+            "Invalid enrollment",
+            reason,
+        );
+    }
+
+    match data.verifier.registry.enroll(kid, owner, jwk) {
+        Ok(key) => HttpResponse::Created().json(json!({
+            "kid": key.kid,
+            "owner": key.owner,
+            "jwk_thumbprint": thumbprint,
+        })),
+        Err(e) => registry_error(e),
+    }
+}
+
+/// Rejects kids a proof or a threshold challenge could not name: `did:` kids
+/// are resolved as DIDs and `kids` lists are comma-separated.
+fn check_kid(kid: &str) -> Result<(), &'static str> {
+    if kid.is_empty() {
+        Err("kid must not be empty")
+    } else if did::is_did(kid) {
+        Err("kid must not be a DID")
+    } else if kid.contains(',') {
+        Err("kid must not contain a comma")
+    } else {
+        Ok(())
+    }
+}
+
+fn registry_error(e: RegistryError) -> HttpResponse {
+    let (status, code, title) = match e {
+        RegistryError::KidTaken(_) => {
+            (StatusCode::CONFLICT, "kid_taken", "Key ID already enrolled")
+        }
+        RegistryError::Superseded(_) => (
+            StatusCode::CONFLICT,
+            "key_superseded",
+            "Key already rotated",
+        ),
+        RegistryError::UnknownKid(_) => (StatusCode::NOT_FOUND, "unknown_kid", "Unknown key ID"),
+        RegistryError::Storage(_) => {
+            log::error!("{}", e);
+            return request_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "registry_unavailable",
+                "Key registry unavailable",
+                "the key registry could not be updated",
+            );
         }
     };
+    request_error(status, code, title, &e.to_string())
+}
 
-    let (payload, _header) = match jwt::decode_with_verifier(token, &verifier) {
-        Ok(tuple) => tuple,
-        Err(e) => {
-            return HttpResponse::BadRequest()
-                .json(json!({ "error": e.to_string() }))
+/// Shows an enrolled key together with its rotation history.
+async fn get_key(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    kid: web::Path<String>,
+) -> impl Responder {
+    if let Some(denied) = admin_token_error(&req, &data) {
+        return denied;
+    }
+    match data.verifier.registry.get(&kid) {
+        Some(key) => HttpResponse::Ok().json(key),
+        None => registry_error(RegistryError::UnknownKid(kid.into_inner())),
+    }
+}
+
+/// Replaces an enrolled key with a proof signed by it and by its
+/// replacement; see [`Verifier::rotate_key`]. A bound client certificate
+/// must carry the enrolled key.
+async fn rotate_key(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    body: Result<String, actix_web::Error>,
+) -> impl Responder {
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => return malformed_request(e.as_response_error()),
+    };
+    let proof_policy = match request_policy(&req, &data) {
+        Ok(policy) => policy,
+        Err(e) => return client_certificate_error(e),
+    };
+    match data.verifier.rotate_key(body.trim(), &proof_policy).await {
+        Ok(key) => {
+            let rotation = key.history.last();
+            HttpResponse::Ok().json(json!({
+                "status": "success",
+                "kid": key.kid,
+                "owner": key.owner,
+                "previous_jkt": rotation.map(|r| &r.previous_jkt),
+                "jwk_thumbprint": rotation.map(|r| &r.new_jkt),
+                "rotations": key.history.len(),
+            }))
         }
+        Err(RotationError::Invalid(detail)) => invalid_rotation(&detail),
+        Err(RotationError::Proof(e)) => proof_error(e),
+        Err(RotationError::Registry(e)) => registry_error(e),
+    }
+}
+
+fn invalid_rotation(detail: &str) -> HttpResponse {
+    request_error(
+        StatusCode::BAD_REQUEST,
+        "invalid_rotation",
+        "Invalid rotation proof",
+        detail,
+    )
+}
+
+/// Enrollment and key lookup need `Authorization: Bearer <REGISTRY_ADMIN_TOKEN>`;
+/// without a configured token they are disabled.
+fn admin_token_error(req: &HttpRequest, data: &AppState) -> Option<HttpResponse> {
+    let Some(expected) = &data.registry_admin_token else {
+        return Some(request_error(
+            StatusCode::FORBIDDEN,
+            "registry_admin_disabled",
+            "Key registry administration disabled",
+            "set REGISTRY_ADMIN_TOKEN to enroll and look up keys",
+        ));
     };
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if presented.is_some_and(|token| constant_time_eq(token, expected)) {
+        None
+    } else {
+        Some(request_error(
+            StatusCode::UNAUTHORIZED,
+            "admin_token_required",
+            "Admin token required",
+            "this endpoint requires the admin token",
+        ))
+    }
+}
+
+/// Compares SHA-256 digests so neither the contents nor the length of the
+/// expected token leak through timing.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
 
-    let nonce = match payload.claim("nonce").and_then(|v| v.as_str()) {
-        Some(n) => n,
+/// Verifies an SD-JWT presentation whose Key Binding JWT proves possession
+/// of the key in the credential's `cnf.jwk`.
+async fn verify_sd_jwt_kb(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    body: Result<String, actix_web::Error>,
+) -> impl Responder {
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => return malformed_request(e.as_response_error()),
+    };
+    let issuers = match &data.sd_jwt_issuers {
+        Some(issuers) => issuers,
+        None => {
+            return request_error(
+                StatusCode::BAD_REQUEST,
+                "sd_jwt_not_enabled",
+                "SD-JWT not enabled",
+                "this verifier does not accept SD-JWT presentations",
+            )
+        }
+    };
+    let proof_policy = match request_policy(&req, &data) {
+        Ok(policy) => policy,
+        Err(e) => return client_certificate_error(e),
+    };
+    // Without an identifier of its own, the verifier cannot tell a KB-JWT
+    // meant for it from one replayed from another verifier.
+    let audience = match &proof_policy.binding.audience {
+        Some(audience) => audience,
         None => {
-            return HttpResponse::BadRequest()
-                .json(json!({ "error": "nonce not found in claims" }))
+            return request_error(
+                StatusCode::BAD_REQUEST,
+                "sd_jwt_not_enabled",
+                "SD-JWT not enabled",
+                "SD-JWT presentations need PROOF_AUDIENCE to be set",
+            )
         }
     };
+    let presentation = match data
+        .verifier
+        .verify_presentation(body.trim(), issuers, audience, &proof_policy)
+        .await
+    {
+        Ok(presentation) => presentation,
+        Err(PresentationError::Credential(detail)) => return invalid_credential(&detail),
+        Err(PresentationError::Proof(e)) => return proof_error(e),
+    };
 
-    let mut nonces = data.nonces.lock().unwrap();
-    if nonces.remove(nonce) {
-        HttpResponse::Ok().json(json!({ "status": "success" }))
-    } else {
-        HttpResponse::BadRequest().json(json!({ "error": "invalid or reused nonce" }))
+    let proof = &presentation.proof;
+    let mut response = match success_response(&data, proof, proof.nonce().unwrap_or_default()) {
+        Ok(response) => response,
+        Err(e) => return receipt_error(e),
+    };
+    response["credential"] = json!(presentation.credential.claims_set());
+    response["disclosures"] = json!(presentation.disclosures);
+    HttpResponse::Ok().json(response)
+}
+
+fn invalid_credential(detail: &str) -> HttpResponse {
+    request_error(
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "invalid_credential",
+        "Invalid credential",
+        detail,
+    )
+}
+
+/// Builds the body returned for a verified proof, including a receipt when
+/// receipts are configured.
+fn success_response(
+    data: &AppState,
+    proof: &VerifiedProof,
+    nonce: &str,
+) -> Result<Value, JoseError> {
+    let mut response = key_summary(proof);
+    response["status"] = json!("success");
+    response["claims"] = json!(proof.payload.claims_set());
+    if let Some(receipt) = issue_receipt(data, proof, nonce)? {
+        response["receipt"] = json!(receipt);
     }
+    Ok(response)
 }
 
-async fn prove_ownership() -> Result<(), Box<dyn std::error::Error>> {
-    let client = reqwest::Client::new();
+/// Describes the key a proof was verified with.
+fn key_summary(proof: &VerifiedProof) -> Value {
+    let mut response = json!({
+        "jwk_thumbprint": proof.thumbprint,
+        "kty": proof.jwk.key_type(),
+        "crv": proof.jwk.curve(),
+    });
+    if let Some(key) = &proof.enrolled {
+        response["kid"] = json!(key.kid);
+        response["owner"] = json!(key.owner);
+    }
+    if let Some(cert) = &proof.certificate {
+        response["subject"] = json!(cert.subject);
+        response["sans"] = json!(cert.sans);
+    }
+    if let Some(did) = &proof.did {
+        response["did"] = json!(did);
+    }
+    response
+}
 
-    let nonce_resp = client
-        .get("http://127.0.0.1:8080/nonce")
-        .send()
-        .await?
-        .json::<serde_json::Value>()
-        .await?;
-    let nonce = nonce_resp["nonce"]
-        .as_str()
-        .ok_or_else(|| anyhow!("nonce field missing"))?;
+/// Issues a receipt for a verified proof when receipts are configured.
+fn issue_receipt(
+    data: &AppState,
+    proof: &VerifiedProof,
+    nonce: &str,
+) -> Result<Option<String>, JoseError> {
+    match &data.receipts {
+        Some(receipts) => receipts.issue(&proof.thumbprint, nonce).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Answers a proof that verified but could not be given a receipt.
+fn receipt_error(e: JoseError) -> HttpResponse {
+    log::error!("failed to issue receipt: {}", e);
+    request_error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "receipt_unavailable",
+        "Receipt unavailable",
+        "the proof verified but no receipt could be issued",
+    )
+}
 
-    let private_key = jwk::Jwk::generate_ec_key(josekit::jwk::alg::ec::EcCurve::P256)?;
-    let public_key = private_key.to_public_key()?;
+/// Verifies a DPoP proof bound to this very request. The proof must carry a
+/// nonce from `/nonce` or from a previous `DPoP-Nonce` response header.
+async fn verify_dpop(
+    req: &HttpRequest,
+    data: &AppState,
+    policy: &DpopPolicy,
+    proof_policy: &Policy,
+    token: &str,
+) -> HttpResponse {
+    let access_token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("DPoP "));
+    let request = dpop::DpopRequest {
+        method: req.method().as_str(),
+        url: &policy.htu,
+        access_token,
+    };
+    let proof = match data
+        .verifier
+        .verify_dpop(token, &request, &policy.jtis, proof_policy)
+        .await
+    {
+        Ok(proof) => proof,
+        Err(e) => return dpop_error(data, e).await,
+    };
 
+    let response = match success_response(data, &proof, proof.nonce().unwrap_or_default()) {
+        Ok(response) => response,
+        Err(e) => return receipt_error(e),
+    };
+    let mut builder = HttpResponse::Ok();
+    if let Some(next) = issue_nonce(data).await {
+        builder.insert_header((DPOP_NONCE_HEADER, next));
+    }
+    builder.json(response)
+}
 
-    let mut header = JwsHeader::new();
-    header.set_token_type("JWT");
-    header.set_jwk(public_key);
+/// Verifies a wallet's `openid4vci-proof+jwt` key proof. Its `nonce` must be
+/// a `c_nonce` issued by `/nonce`.
+async fn verify_openid4vci(
+    data: &AppState,
+    policy: &Oid4vciPolicy,
+    proof_policy: &Policy,
+    token: &str,
+) -> HttpResponse {
+    let proof = match data
+        .verifier
+        .verify_openid4vci(token, policy, proof_policy)
+        .await
+    {
+        Ok(proof) => proof,
+        Err(e) => return oid4vci_error(data, e).await,
+    };
+    match success_response(data, &proof, proof.nonce().unwrap_or_default()) {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => receipt_error(e),
+    }
+}
 
-    let mut payload = jwt::JwtPayload::new();
-    payload.set_claim("nonce", Some(json!(nonce)))?;
+/// Renders an OpenID4VCI proof failure as a Credential Error Response with a
+/// fresh `c_nonce`, so the wallet can retry without another round trip.
+async fn oid4vci_error(data: &AppState, error: Oid4vciError) -> HttpResponse {
+    if let Oid4vciError::NonceStore(e) = error {
+        return proof_error(ProofError::NonceStore(e));
+    }
+    let mut body = json!({
+        "error": error.code(),
+        "error_description": error.to_string(),
+    });
+    if let Some(nonce) = issue_nonce(data).await {
+        body["c_nonce"] = json!(nonce);
+        body["c_nonce_expires_in"] = json!(data.nonce_ttl.as_secs());
+    }
+    HttpResponse::BadRequest()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .json(body)
+}
 
-    let signer = ES256.signer_from_jwk(&private_key)?;
-    let signed_jwt = jwt::encode_with_signer(&payload, &header, &signer)?;
+/// The OpenID4VCI Nonce Endpoint: the same nonces as `GET /nonce`, named
+/// `c_nonce`.
+async fn generate_c_nonce(data: web::Data<AppState>) -> impl Responder {
+    match issue_nonce(&data).await {
+        Some(nonce) => HttpResponse::Ok()
+            .insert_header((header::CACHE_CONTROL, "no-store"))
+            .json(json!({ "c_nonce": nonce })),
+        None => request_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "nonce_store_unavailable",
+            "Nonce store unavailable",
+            "nonce store unavailable",
+        ),
+    }
+}
 
-    let verify_resp = client
-        .post("http://127.0.0.1:8080/verify")
-        .body(signed_jwt)
-        .send()
-        .await?;
-    println!("Verification response: {}", verify_resp.status());
-    Ok(())
+/// Renders a DPoP failure as an RFC 9449 error: `error` and
+/// `error_description` in the body, and the code in `WWW-Authenticate`.
+/// `use_dpop_nonce` answers 401 with a fresh nonce in the `DPoP-Nonce`
+/// header so the client can retry.
+async fn dpop_error(data: &AppState, error: DpopError) -> HttpResponse {
+    if let DpopError::NonceStore(e) = error {
+        return proof_error(ProofError::NonceStore(e));
+    }
+    let use_nonce = matches!(error, DpopError::UseNonce(_));
+    let mut builder = if use_nonce {
+        HttpResponse::Unauthorized()
+    } else {
+        HttpResponse::BadRequest()
+    };
+    builder.insert_header((
+        header::WWW_AUTHENTICATE,
+        format!("DPoP error=\"{}\"", error.code()),
+    ));
+    if use_nonce {
+        if let Some(nonce) = issue_nonce(data).await {
+            builder.insert_header((DPOP_NONCE_HEADER, nonce));
+        }
+    }
+    builder.json(json!({
+        "error": error.code(),
+        "error_description": error.to_string(),
+    }))
+}
+
+async fn issue_nonce(data: &AppState) -> Option<String> {
+    match data
+        .verifier
+        .issue_nonce(SystemTime::now() + data.nonce_ttl, None)
+        .await
+    {
+        Ok(nonce) => Some(nonce),
+        Err(e) => {
+            log::error!("failed to issue nonce: {}", e);
+            None
+        }
+    }
+}
+
+/// Builds the receipt issuer; receipts are only issued when signing keys
+/// are configured.
+fn receipt_issuer(config: &Config) -> anyhow::Result<Option<ReceiptIssuer>> {
+    let receipts = match &config.receipts {
+        Some(receipts) => receipts,
+        None => return Ok(None),
+    };
+    let keys = KeyRing::load(&receipts.keys)
+        .with_context(|| format!("loading receipt keys from {}", receipts.keys.display()))?;
+    Ok(Some(ReceiptIssuer {
+        keys: Arc::new(keys),
+        ttl: receipts.ttl,
+        issuer: receipts.issuer.clone(),
+    }))
+}
+
+/// Everything the handlers share, built from `config`. Loads every file the
+/// configuration names.
+fn app_state(config: &Config, nonces: Arc<dyn NonceStore>) -> anyhow::Result<AppState> {
+    let registry = match &config.registry_file {
+        Some(path) => KeyRegistry::open(path).context("opening the key registry")?,
+        None => KeyRegistry::new(),
+    };
+    let trust_anchors = match &config.trust_anchors {
+        Some(path) => Some(TrustAnchors::load(path).context("loading X.509 trust anchors")?),
+        None => None,
+    };
+    let remote_keys = match &config.remote_keys {
+        Some(policy) => Some(RemoteKeys::new(policy.clone()).context("configuring remote keys")?),
+        None => None,
+    };
+    let sd_jwt_issuers = match &config.sd_jwt_issuer_keys {
+        Some(path) => Some(IssuerKeys::load(path).context("loading SD-JWT issuer keys")?),
+        None => None,
+    };
+    let verifier = Verifier {
+        nonces,
+        registry: Arc::new(registry),
+        trust_anchors,
+        remote_keys,
+    };
+    Ok(AppState {
+        verifier,
+        policy: Policy {
+            allowed_algs: config.allowed_algs.clone(),
+            time: config.time.clone(),
+            binding: config.binding.clone(),
+            bound_jkt: None,
+        },
+        nonce_ttl: config.nonce_ttl,
+        receipts: receipt_issuer(config)?,
+        dpop: config.dpop.as_ref().map(|dpop| DpopPolicy {
+            htu: dpop.htu.clone(),
+            jtis: JtiCache::new(),
+        }),
+        oid4vci: config
+            .oid4vci_credential_issuer
+            .clone()
+            .map(|credential_issuer| Oid4vciPolicy { credential_issuer }),
+        sd_jwt_issuers,
+        registry_admin_token: config.registry_admin_token.clone(),
+        require_proof_key_match: config
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.require_proof_key_match),
+    })
+}
+
+/// The key ownership verifier service.
+#[derive(Parser)]
+#[command(name = "kop-verifier", version)]
+struct Cli {
+    /// TOML configuration file. Environment variables override its settings.
+    #[arg(long, env = "KOP_CONFIG")]
+    config: Option<PathBuf>,
+    /// Validate the configuration and every file it names, then exit
+    /// without opening the nonce store or listening.
+    #[arg(long)]
+    check_config: bool,
 }
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    let state = web::Data::new(AppState {
-        nonces: Mutex::new(HashSet::new()),
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if cli.check_config {
+        // The nonce store spec was checked by `Config::load`; a memory store
+        // stands in for it so that checking creates no files.
+        let nonces: Arc<dyn NonceStore> = Arc::new(store::InMemoryNonceStore::new());
+        let checked = app_state(&config, nonces).and_then(|_| match &config.tls {
+            Some(tls) => tls::server_config(tls).map(drop),
+            None => Ok(()),
+        });
+        return match checked {
+            Ok(()) => {
+                println!("configuration OK");
+                println!("  bind: {}", config.bind.join(", "));
+                println!(
+                    "  allowed algs: {}",
+                    config
+                        .allowed_algs
+                        .iter()
+                        .map(|alg| alg.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                println!(
+                    "  proof max age: {}s, clock skew: {}s",
+                    config.time.max_age.as_secs(),
+                    config.time.skew.as_secs()
+                );
+                match &config.binding.audience {
+                    Some(audience) => println!("  proof audience: {}", audience),
+                    None => println!("  proof audience: any"),
+                }
+                println!("  nonce TTL: {}s", config.nonce_ttl.as_secs());
+                println!("  nonce store: {}", config.nonce_store);
+                match &config.receipts {
+                    Some(receipts) => println!("  receipt keys: {}", receipts.keys.display()),
+                    None => println!("  receipts: disabled"),
+                }
+                match &config.tls {
+                    Some(tls) => {
+                        println!("  TLS certificate: {}", tls.cert.display());
+                        match &tls.client_auth {
+                            ClientAuth::None => println!("  client certificates: not requested"),
+                            ClientAuth::Optional(ca) => {
+                                println!("  client certificates: optional, CA {}", ca.display())
+                            }
+                            ClientAuth::Required(ca) => {
+                                println!("  client certificates: required, CA {}", ca.display())
+                            }
+                        }
+                        if tls.require_proof_key_match {
+                            println!("  proofs must match the client certificate's key");
+                        }
+                    }
+                    None => println!("  TLS: disabled"),
+                }
+                match &config.registry_file {
+                    Some(path) => println!("  key registry: {}", path.display()),
+                    None => println!("  key registry: in memory"),
+                }
+                if config.registry_admin_token.is_none() {
+                    println!("  enrollment: disabled, no admin token");
+                }
+                if let Some(path) = &config.trust_anchors {
+                    println!("  x5c trust anchors: {}", path.display());
+                }
+                match &config.remote_keys {
+                    Some(policy) => {
+                        println!("  remote key hosts: {}", policy.allowed_hosts.join(", "))
+                    }
+                    None => println!("  remote keys: disabled"),
+                }
+                println!(
+                    "  DPoP: {}",
+                    if config.dpop.is_some() {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+                if let Some(issuer) = &config.oid4vci_credential_issuer {
+                    println!("  OpenID4VCI credential issuer: {}", issuer);
+                }
+                if let Some(path) = &config.sd_jwt_issuer_keys {
+                    println!("  SD-JWT issuer keys: {}", path.display());
+                }
+                println!("  log level: {}", config.log_level);
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{:#}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    env_logger::Builder::new()
+        .parse_filters(&config.log_level)
+        .init();
+    match serve(config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            log::error!("{:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn serve(config: Config) -> anyhow::Result<()> {
+    let nonces = store::open(&config.nonce_store)
+        .with_context(|| format!("opening nonce store {}", config.nonce_store))?;
+    let state = web::Data::new(app_state(&config, nonces)?);
+    let tls = match &config.tls {
+        Some(tls) => Some(tls::server_config(tls)?),
+        None => None,
+    };
+
+    // Picks up renewed TLS certificates without a restart.
+    if let Some((_, cert)) = &tls {
+        let cert = cert.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = tokio::time::interval(KEY_RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                match cert.reload_if_changed() {
+                    Ok(true) => log::info!("reloaded TLS certificate"),
+                    Ok(false) => {}
+                    Err(e) => log::error!("failed to reload TLS certificate: {:#}", e),
+                }
+            }
+        });
+    }
+
+    // Picks up rotated receipt keys without a restart.
+    if let Some(receipts) = &state.receipts {
+        let keys = receipts.keys.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = tokio::time::interval(KEY_RELOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                match keys.reload_if_changed() {
+                    Ok(true) => log::info!("reloaded receipt signing keys"),
+                    Ok(false) => {}
+                    Err(e) => log::error!("failed to reload receipt signing keys: {}", e),
+                }
+            }
+        });
+    }
+
+    // Abandoned challenges would otherwise sit in the map forever.
+    let sweeper_state = state.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = tokio::time::interval(sweeper_state.nonce_ttl / 2);
+        loop {
+            interval.tick().await;
+            let now = SystemTime::now();
+            if let Err(e) = sweeper_state.verifier.nonces.sweep(now).await {
+                log::error!("nonce sweep failed: {}", e);
+            }
+        }
     });
 
-    let server = HttpServer::new(move || {
+    let mut server = HttpServer::new(move || {
         App::new()
+            .wrap(middleware::Logger::default())
             .app_data(state.clone())
+            .app_data(web::JsonConfig::default().error_handler(|e, _| {
+                let response = malformed_request(&e);
+                InternalError::from_response(e, response).into()
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| {
+                let response = malformed_request(&e);
+                InternalError::from_response(e, response).into()
+            }))
             .route("/nonce", web::get().to(generate_nonce))
+            .route("/nonce", web::post().to(generate_c_nonce))
             .route("/verify", web::post().to(verify_attestation))
+            .route("/verify/sd-jwt-kb", web::post().to(verify_sd_jwt_kb))
+            .route("/keys", web::post().to(enroll_key))
+            .route("/keys/rotate", web::post().to(rotate_key))
+            .route("/keys/{kid}", web::get().to(get_key))
+            .route("/.well-known/jwks.json", web::get().to(jwks))
     })
-    .bind("127.0.0.1:8080")?
-    .run();
-
-    let holder = tokio::spawn(async {
-        if let Err(e) = prove_ownership().await {
-            eprintln!("Holder script failed: {}", e);
+    .on_connect(tls::on_connect);
+    for addr in &config.bind {
+        server = match &tls {
+            Some((server_config, _)) => {
+                server.bind_rustls_0_23(addr.as_str(), server_config.clone())
+            }
+            None => server.bind(addr.as_str()),
         }
-    });
-
-    let (server_res, holder_res) = tokio::join!(server, holder);
-    server_res?;
-    holder_res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        .with_context(|| format!("binding {}", addr))?;
+        log::info!(
+            "listening on {}://{}",
+            if tls.is_some() { "https" } else { "http" },
+            addr
+        );
+    }
+    server.run().await?;
     Ok(())
 }
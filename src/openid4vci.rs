@@ -0,0 +1,89 @@
+//! Key proofs from OpenID for Verifiable Credential Issuance
+//! (`openid4vci-proof+jwt`).
+
+use josekit::{jws::JwsHeader, jwt::JwtPayload};
+use std::fmt;
+
+pub const PROOF_TYPE: &str = "openid4vci-proof+jwt";
+
+#[derive(Debug)]
+pub enum Oid4vciError {
+    /// The proof is malformed, badly signed or addressed to someone else.
+    InvalidProof(String),
+    /// The proof lacks a current `c_nonce`; the wallet should retry with the
+    /// fresh one returned alongside the error.
+    InvalidNonce(String),
+    /// The nonce store failed; not the wallet's fault.
+    NonceStore(anyhow::Error),
+}
+
+impl Oid4vciError {
+    /// The error code from the Credential Endpoint error response. A failed
+    /// nonce store has none of its own.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Oid4vciError::InvalidProof(_) => "invalid_proof",
+            Oid4vciError::InvalidNonce(_) => "invalid_nonce",
+            Oid4vciError::NonceStore(_) => "nonce_store_unavailable",
+        }
+    }
+}
+
+impl fmt::Display for Oid4vciError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Oid4vciError::InvalidProof(msg) | Oid4vciError::InvalidNonce(msg) => f.write_str(msg),
+            Oid4vciError::NonceStore(_) => f.write_str("nonce store unavailable"),
+        }
+    }
+}
+
+/// Settings for the proof profile of one Credential Issuer.
+#[derive(Debug, Clone)]
+pub struct Oid4vciPolicy {
+    /// The Credential Issuer Identifier proofs must name in `aud`.
+    pub credential_issuer: String,
+}
+
+/// Checks the header and claims specific to the profile. Signature, time
+/// claims and `c_nonce` are checked by the caller.
+pub fn check_proof(
+    header: &JwsHeader,
+    payload: &JwtPayload,
+    policy: &Oid4vciPolicy,
+) -> Result<(), Oid4vciError> {
+    if header.token_type() != Some(PROOF_TYPE) {
+        return Err(Oid4vciError::InvalidProof(format!(
+            "typ must be {}",
+            PROOF_TYPE
+        )));
+    }
+
+    let key_members = ["jwk", "kid", "x5c"]
+        .iter()
+        .filter(|name| header.claim(name).is_some())
+        .count();
+    if key_members != 1 {
+        return Err(Oid4vciError::InvalidProof(
+            "exactly one of jwk, kid and x5c must be present".to_string(),
+        ));
+    }
+
+    match payload.audience() {
+        Some(audience) if audience == [policy.credential_issuer.as_str()] => {}
+        _ => {
+            return Err(Oid4vciError::InvalidProof(format!(
+                "aud must be {}",
+                policy.credential_issuer
+            )))
+        }
+    }
+
+    if payload.claim("iat").is_none() {
+        return Err(Oid4vciError::InvalidProof(
+            "iat missing in claims".to_string(),
+        ));
+    }
+
+    Ok(())
+}
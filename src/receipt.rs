@@ -0,0 +1,54 @@
+//! Verification receipts: short-lived JWTs the verifier signs after a
+//! successful proof, so relying services can trust it offline.
+
+use crate::keyring::KeyRing;
+use base64::{engine::general_purpose, Engine as _};
+use josekit::{
+    jws::JwsHeader,
+    jwt::{self, JwtPayload},
+    JoseError,
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+pub const RECEIPT_TYPE: &str = "kop-receipt+jwt";
+
+pub struct ReceiptIssuer {
+    pub keys: Arc<KeyRing>,
+    pub ttl: Duration,
+    /// Value of the `iss` claim, if configured.
+    pub issuer: Option<String>,
+}
+
+impl ReceiptIssuer {
+    /// Signs a receipt binding the holder key `jkt` to the consumed `nonce`.
+    pub fn issue(&self, jkt: &str, nonce: &str) -> Result<String, JoseError> {
+        let key = self.keys.active();
+        let now = SystemTime::now();
+
+        let mut header = JwsHeader::new();
+        header.set_token_type(RECEIPT_TYPE);
+        header.set_key_id(&key.kid);
+
+        let mut payload = JwtPayload::new();
+        if let Some(issuer) = &self.issuer {
+            payload.set_issuer(issuer);
+        }
+        payload.set_issued_at(&now);
+        payload.set_expires_at(&(now + self.ttl));
+        payload.set_jwt_id(Uuid::new_v4().to_string());
+        payload.set_claim("cnf", Some(json!({ "jkt": jkt })))?;
+        payload.set_claim("nonce_hash", Some(json!(nonce_hash(nonce))))?;
+
+        let signer = key.alg.signer_from_jwk(&key.jwk)?;
+        jwt::encode_with_signer(&payload, &header, &*signer)
+    }
+}
+
+/// base64url SHA-256 of the nonce, so receipts do not disclose the nonce itself.
+fn nonce_hash(nonce: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(nonce.as_bytes()))
+}
@@ -0,0 +1,217 @@
+//! Enrolled public keys, looked up by `kid`.
+
+use crate::verifier::ProofError;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A public key enrolled on behalf of an owner.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisteredKey {
+    pub kid: String,
+    /// Identifier of the account, device or service the key belongs to.
+    pub owner: String,
+    pub jwk: Map<String, Value>,
+    /// Unix seconds at enrollment.
+    pub enrolled_at: u64,
+    /// Rotations that led to the current `jwk`, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<Rotation>,
+}
+
+/// One step of a key's continuity chain: the previous key endorsed its
+/// replacement in a proof both keys signed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rotation {
+    /// RFC 7638 thumbprint of the replaced key.
+    pub previous_jkt: String,
+    /// RFC 7638 thumbprint of the replacing key.
+    pub new_jkt: String,
+    pub previous_jwk: Map<String, Value>,
+    /// Unix seconds at rotation.
+    pub rotated_at: u64,
+    /// The JWS JSON proof signed by both keys, kept so the rotation can be
+    /// verified again later.
+    pub proof: String,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    /// Another key is already enrolled under this `kid`.
+    KidTaken(String),
+    /// No key is enrolled under this `kid`.
+    UnknownKid(String),
+    /// The enrolled key changed while a rotation was being verified.
+    Superseded(String),
+    /// The registry could not be written to disk.
+    Storage(anyhow::Error),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::KidTaken(kid) => write!(f, "kid {:?} is already enrolled", kid),
+            RegistryError::UnknownKid(kid) => write!(f, "unknown kid {:?}", kid),
+            RegistryError::Superseded(kid) => {
+                write!(f, "the key enrolled under kid {:?} has changed", kid)
+            }
+            RegistryError::Storage(e) => write!(f, "failed to persist key registry: {}", e),
+        }
+    }
+}
+
+/// Why a key rotation was refused.
+#[derive(Debug)]
+pub enum RotationError {
+    /// The signatures verified but do not form a valid rotation.
+    Invalid(String),
+    /// A signature, its claims or its nonce failed verification.
+    Proof(ProofError),
+    /// The registry refused the new key.
+    Registry(RegistryError),
+}
+
+impl From<ProofError> for RotationError {
+    fn from(e: ProofError) -> Self {
+        RotationError::Proof(e)
+    }
+}
+
+impl fmt::Display for RotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RotationError::Invalid(msg) => f.write_str(msg),
+            RotationError::Proof(e) => write!(f, "{}", e),
+            RotationError::Registry(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Enrolled keys, held in memory and optionally mirrored to a JSON file.
+#[derive(Default)]
+pub struct KeyRegistry {
+    path: Option<PathBuf>,
+    keys: RwLock<HashMap<String, RegisteredKey>>,
+}
+
+impl KeyRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a registry persisted at `path`, creating it on first write.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let keys = if path.exists() {
+            let contents =
+                fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            let entries: Vec<RegisteredKey> = serde_json::from_slice(&contents)
+                .with_context(|| format!("parsing {}", path.display()))?;
+            entries
+                .into_iter()
+                .map(|key| (key.kid.clone(), key))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        Ok(KeyRegistry {
+            path: Some(path),
+            keys: RwLock::new(keys),
+        })
+    }
+
+    pub fn get(&self, kid: &str) -> Option<RegisteredKey> {
+        self.keys.read().unwrap().get(kid).cloned()
+    }
+
+    /// Enrolls `jwk` for `owner` under `kid`. The JWK must already have
+    /// passed the public-key policy.
+    pub fn enroll(
+        &self,
+        kid: String,
+        owner: String,
+        jwk: Map<String, Value>,
+    ) -> Result<RegisteredKey, RegistryError> {
+        let mut keys = self.keys.write().unwrap();
+        if keys.contains_key(&kid) {
+            return Err(RegistryError::KidTaken(kid));
+        }
+        let key = RegisteredKey {
+            kid: kid.clone(),
+            owner,
+            jwk,
+            enrolled_at: unix_secs(),
+            history: Vec::new(),
+        };
+        keys.insert(kid.clone(), key.clone());
+        if let Err(e) = self.persist(&keys) {
+            keys.remove(&kid);
+            return Err(RegistryError::Storage(e));
+        }
+        Ok(key)
+    }
+
+    /// Replaces the key enrolled under `kid` with `new_jwk`, provided it is
+    /// still `expected_jwk`, and records the rotation in its history.
+    pub fn rotate(
+        &self,
+        kid: &str,
+        expected_jwk: &Map<String, Value>,
+        new_jwk: Map<String, Value>,
+        previous_jkt: String,
+        new_jkt: String,
+        proof: String,
+    ) -> Result<RegisteredKey, RegistryError> {
+        let mut keys = self.keys.write().unwrap();
+        let previous = match keys.get(kid) {
+            Some(key) if key.jwk == *expected_jwk => key.clone(),
+            Some(_) => return Err(RegistryError::Superseded(kid.to_owned())),
+            None => return Err(RegistryError::UnknownKid(kid.to_owned())),
+        };
+        let mut key = previous.clone();
+        key.history.push(Rotation {
+            previous_jkt,
+            new_jkt,
+            previous_jwk: std::mem::replace(&mut key.jwk, new_jwk),
+            rotated_at: unix_secs(),
+            proof,
+        });
+        keys.insert(kid.to_owned(), key.clone());
+        if let Err(e) = self.persist(&keys) {
+            keys.insert(kid.to_owned(), previous);
+            return Err(RegistryError::Storage(e));
+        }
+        Ok(key)
+    }
+
+    /// Rewrites the registry file, if any, via a temporary file and rename so
+    /// a crash never leaves it half written.
+    fn persist(&self, keys: &HashMap<String, RegisteredKey>) -> anyhow::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut entries: Vec<&RegisteredKey> = keys.values().collect();
+        entries.sort_by(|a, b| a.kid.cmp(&b.kid));
+        let tmp_path = path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&serde_json::to_vec_pretty(&entries)?)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,332 @@
+//! Fetching holder keys from `jku` (JWK Set) and `x5u` (PEM chain) URLs.
+
+use base64::{engine::general_purpose, Engine as _};
+use reqwest::{header, redirect, StatusCode, Url};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use x509_parser::pem::Pem;
+
+/// Limits on what the verifier will fetch.
+#[derive(Debug, Clone)]
+pub struct RemoteKeyPolicy {
+    /// Hosts keys may be fetched from, as `host` or `host:port`.
+    pub allowed_hosts: Vec<String>,
+    /// Permit plain `http` URLs. Only meant for tests against a local
+    /// stand-in server.
+    pub allow_http: bool,
+    pub timeout: Duration,
+    pub max_bytes: usize,
+    /// Cache lifetime when the response has no `Cache-Control: max-age`.
+    pub default_ttl: Duration,
+    /// Upper bound on any cache lifetime.
+    pub max_ttl: Duration,
+    /// Most responses cached at once; the least recently used is evicted.
+    pub max_cache_entries: usize,
+    /// Most fetches from one host per `fetch_window`. Cache hits are free.
+    pub max_fetches_per_host: u32,
+    pub fetch_window: Duration,
+}
+
+impl Default for RemoteKeyPolicy {
+    fn default() -> Self {
+        RemoteKeyPolicy {
+            allowed_hosts: Vec::new(),
+            allow_http: false,
+            timeout: Duration::from_secs(3),
+            max_bytes: 64 * 1024,
+            default_ttl: Duration::from_secs(300),
+            max_ttl: Duration::from_secs(24 * 60 * 60),
+            max_cache_entries: 256,
+            max_fetches_per_host: 10,
+            fetch_window: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    /// The URL does not parse, or is not `https`.
+    InvalidUrl(String),
+    /// The host is not on the allowlist.
+    HostNotAllowed(String),
+    Timeout,
+    /// The connection failed.
+    Unreachable(String),
+    /// The server answered with a non-success status.
+    Status(StatusCode),
+    /// The response exceeded the size limit.
+    TooLarge,
+    /// The response is not a JWK Set or PEM chain.
+    InvalidContent(String),
+    /// The JWK Set has no key with the requested `kid`.
+    KeyNotFound(String),
+    /// The host has used up its fetches for the current window.
+    RateLimited(String),
+}
+
+impl FetchError {
+    /// Stable machine-readable code for the failure.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FetchError::InvalidUrl(_) => "remote_key_invalid_url",
+            FetchError::HostNotAllowed(_) => "remote_key_host_not_allowed",
+            FetchError::Timeout => "remote_key_timeout",
+            FetchError::Unreachable(_) => "remote_key_unreachable",
+            FetchError::Status(_) => "remote_key_http_error",
+            FetchError::TooLarge => "remote_key_too_large",
+            FetchError::InvalidContent(_) => "remote_key_invalid_content",
+            FetchError::KeyNotFound(_) => "remote_key_kid_not_found",
+            FetchError::RateLimited(_) => "remote_key_rate_limited",
+        }
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::InvalidUrl(msg) => write!(f, "invalid key URL: {}", msg),
+            FetchError::HostNotAllowed(host) => write!(f, "key host {:?} is not allowed", host),
+            FetchError::Timeout => write!(f, "timed out fetching key"),
+            FetchError::Unreachable(msg) => write!(f, "failed to fetch key: {}", msg),
+            FetchError::Status(status) => write!(f, "key URL answered {}", status),
+            FetchError::TooLarge => write!(f, "key response exceeds the size limit"),
+            FetchError::InvalidContent(msg) => write!(f, "invalid key response: {}", msg),
+            FetchError::KeyNotFound(kid) => write!(f, "no key with kid {:?} at jku", kid),
+            FetchError::RateLimited(host) => {
+                write!(f, "too many key fetches from {:?}, try again later", host)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+struct CacheEntry {
+    body: Vec<u8>,
+    expires: Instant,
+    last_used: Instant,
+}
+
+/// Fetches made from one host in the current window.
+struct FetchCount {
+    window_start: Instant,
+    count: u32,
+}
+
+/// Fetches and caches remote key material within a [`RemoteKeyPolicy`].
+pub struct RemoteKeys {
+    client: reqwest::Client,
+    policy: RemoteKeyPolicy,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+    fetches: Mutex<HashMap<String, FetchCount>>,
+}
+
+impl RemoteKeys {
+    pub fn new(policy: RemoteKeyPolicy) -> reqwest::Result<Self> {
+        // Redirects could lead off the allowlist, so none are followed.
+        let client = reqwest::Client::builder()
+            .timeout(policy.timeout)
+            .redirect(redirect::Policy::none())
+            .build()?;
+        Ok(RemoteKeys {
+            client,
+            policy,
+            cache: Mutex::new(HashMap::new()),
+            fetches: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Returns the key named `kid` from the JWK Set at `jku`.
+    pub async fn jwk(&self, jku: &str, kid: &str) -> Result<Map<String, Value>, FetchError> {
+        let body = self.fetch(jku).await?;
+        let set: Value =
+            serde_json::from_slice(&body).map_err(|e| FetchError::InvalidContent(e.to_string()))?;
+        let keys = set
+            .get("keys")
+            .and_then(Value::as_array)
+            .ok_or_else(|| FetchError::InvalidContent("not a JWK Set".to_string()))?;
+        keys.iter()
+            .filter_map(Value::as_object)
+            .find(|key| key.get("kid").and_then(Value::as_str) == Some(kid))
+            .cloned()
+            .ok_or_else(|| FetchError::KeyNotFound(kid.to_owned()))
+    }
+
+    /// Returns the PEM chain at `x5u` in `x5c` form: base64 DER, leaf first.
+    pub async fn x5c(&self, x5u: &str) -> Result<Value, FetchError> {
+        let body = self.fetch(x5u).await?;
+        let mut certs = Vec::new();
+        for pem in Pem::iter_from_buffer(&body) {
+            let pem = pem.map_err(|e| FetchError::InvalidContent(e.to_string()))?;
+            if pem.label == "CERTIFICATE" {
+                certs.push(Value::from(general_purpose::STANDARD.encode(&pem.contents)));
+            }
+        }
+        if certs.is_empty() {
+            return Err(FetchError::InvalidContent(
+                "no certificates in PEM chain".to_string(),
+            ));
+        }
+        Ok(Value::Array(certs))
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Vec<u8>, FetchError> {
+        let url = self.check_url(url)?;
+        if let Some(entry) = self.cache.lock().unwrap().get_mut(url.as_str()) {
+            let now = Instant::now();
+            if entry.expires > now {
+                entry.last_used = now;
+                return Ok(entry.body.clone());
+            }
+        }
+        self.count_fetch(url.host_str().unwrap_or_default())?;
+
+        let mut resp = self
+            .client
+            .get(url.clone())
+            .send()
+            .await
+            .map_err(request_error)?;
+        if !resp.status().is_success() {
+            return Err(FetchError::Status(resp.status()));
+        }
+        if resp
+            .content_length()
+            .is_some_and(|len| len > self.policy.max_bytes as u64)
+        {
+            return Err(FetchError::TooLarge);
+        }
+        let ttl = cache_ttl(resp.headers().get(header::CACHE_CONTROL), &self.policy);
+
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.chunk().await.map_err(request_error)? {
+            if body.len() + chunk.len() > self.policy.max_bytes {
+                return Err(FetchError::TooLarge);
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        cache.retain(|_, entry| entry.expires > now);
+        if let Some(ttl) = ttl {
+            if cache.len() >= self.policy.max_cache_entries && !cache.contains_key(url.as_str()) {
+                let oldest = cache
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    cache.remove(&oldest);
+                }
+            }
+            if self.policy.max_cache_entries > 0 {
+                cache.insert(
+                    url.to_string(),
+                    CacheEntry {
+                        body: body.clone(),
+                        expires: now + ttl,
+                        last_used: now,
+                    },
+                );
+            }
+        }
+        Ok(body)
+    }
+
+    /// Counts a fetch from `host` against its allowance for the window.
+    fn count_fetch(&self, host: &str) -> Result<(), FetchError> {
+        let mut fetches = self.fetches.lock().unwrap();
+        let now = Instant::now();
+        let window = self.policy.fetch_window;
+        fetches.retain(|_, fetch| now.duration_since(fetch.window_start) < window);
+        let fetch = fetches.entry(host.to_owned()).or_insert(FetchCount {
+            window_start: now,
+            count: 0,
+        });
+        if fetch.count >= self.policy.max_fetches_per_host {
+            return Err(FetchError::RateLimited(host.to_owned()));
+        }
+        fetch.count += 1;
+        Ok(())
+    }
+
+    fn check_url(&self, url: &str) -> Result<Url, FetchError> {
+        let url = Url::parse(url).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+        match url.scheme() {
+            "https" => {}
+            "http" if self.policy.allow_http => {}
+            scheme => {
+                return Err(FetchError::InvalidUrl(format!(
+                    "{} URLs are not allowed",
+                    scheme
+                )))
+            }
+        }
+        if !url.username().is_empty() || url.password().is_some() {
+            return Err(FetchError::InvalidUrl(
+                "URLs must not carry credentials".to_string(),
+            ));
+        }
+        // Key documents are static. Without a query or fragment, each URL
+        // has one spelling, so it cannot be varied to bypass the cache.
+        if url.query().is_some() || url.fragment().is_some() {
+            return Err(FetchError::InvalidUrl(
+                "URLs must not carry a query or fragment".to_string(),
+            ));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| FetchError::InvalidUrl("URL has no host".to_string()))?
+            .to_ascii_lowercase();
+        let host_port = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        };
+        let allowed = self.policy.allowed_hosts.iter().any(|entry| {
+            let entry = entry.to_ascii_lowercase();
+            entry == host_port || (url.port().is_none() && entry == host)
+        });
+        if !allowed {
+            return Err(FetchError::HostNotAllowed(host_port));
+        }
+        Ok(url)
+    }
+}
+
+fn request_error(e: reqwest::Error) -> FetchError {
+    if e.is_timeout() {
+        FetchError::Timeout
+    } else {
+        FetchError::Unreachable(e.to_string())
+    }
+}
+
+/// How long a response may be cached according to its `Cache-Control`, or
+/// `None` if it must not be cached.
+fn cache_ttl(
+    cache_control: Option<&header::HeaderValue>,
+    policy: &RemoteKeyPolicy,
+) -> Option<Duration> {
+    let value = match cache_control.and_then(|v| v.to_str().ok()) {
+        Some(value) => value,
+        None => return Some(policy.default_ttl),
+    };
+    let mut ttl = policy.default_ttl;
+    for directive in value.split(',').map(str::trim) {
+        let directive = directive.to_ascii_lowercase();
+        if directive == "no-store" || directive == "no-cache" {
+            return None;
+        }
+        if let Some(secs) = directive.strip_prefix("max-age=") {
+            match secs.trim_matches('"').parse::<u64>() {
+                Ok(0) => return None,
+                Ok(secs) => ttl = Duration::from_secs(secs),
+                Err(_) => {}
+            }
+        }
+    }
+    Some(ttl.min(policy.max_ttl))
+}
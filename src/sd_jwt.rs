@@ -0,0 +1,235 @@
+//! SD-JWT presentations with Key Binding JWTs.
+
+use crate::algs::ProofAlg;
+use crate::verifier::{ProofError, VerifiedProof};
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose, Engine as _};
+use josekit::{
+    jwk::Jwk,
+    jws::JwsHeader,
+    jwt::{self, JwtPayload},
+};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+pub const KB_TYPE: &str = "kb+jwt";
+
+/// A presentation split into its parts: `<issuer-jwt>~<disclosure>~...~<kb-jwt>`.
+pub struct Presentation<'a> {
+    pub issuer_jwt: &'a str,
+    pub disclosures: Vec<&'a str>,
+    pub kb_jwt: &'a str,
+    /// base64url SHA-256 over everything before the KB-JWT, as bound by its
+    /// `sd_hash` claim.
+    pub sd_hash: String,
+}
+
+/// A presentation whose credential and Key Binding JWT both verified.
+pub struct VerifiedPresentation {
+    /// Payload of the issuer-signed JWT.
+    pub credential: JwtPayload,
+    /// The decoded disclosures.
+    pub disclosures: Vec<Value>,
+    /// The Key Binding JWT, verified with the credential's `cnf.jwk`.
+    pub proof: VerifiedProof,
+}
+
+/// Why a presentation was refused.
+#[derive(Debug)]
+pub enum PresentationError {
+    /// The issuer-signed JWT or its disclosures are invalid.
+    Credential(String),
+    /// The Key Binding JWT failed verification, or the presentation cannot
+    /// be parsed.
+    Proof(ProofError),
+}
+
+impl From<ProofError> for PresentationError {
+    fn from(e: ProofError) -> Self {
+        PresentationError::Proof(e)
+    }
+}
+
+impl fmt::Display for PresentationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresentationError::Credential(msg) => f.write_str(msg),
+            PresentationError::Proof(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Presentation<'_>, String> {
+    let (disclosed, kb_jwt) = input
+        .rsplit_once('~')
+        .ok_or_else(|| "SD-JWT presentation must contain ~ separators".to_string())?;
+    if kb_jwt.is_empty() {
+        return Err("presentation has no Key Binding JWT".to_string());
+    }
+    let mut parts = disclosed.split('~');
+    let issuer_jwt = parts.next().unwrap_or_default();
+    if issuer_jwt.is_empty() {
+        return Err("presentation has no issuer-signed JWT".to_string());
+    }
+    let disclosures: Vec<&str> = parts.collect();
+    if disclosures.iter().any(|d| d.is_empty()) {
+        return Err("presentation contains an empty disclosure".to_string());
+    }
+
+    let signed_part = &input[..input.len() - kb_jwt.len()];
+    Ok(Presentation {
+        issuer_jwt,
+        disclosures,
+        kb_jwt,
+        sd_hash: b64_sha256(signed_part),
+    })
+}
+
+fn b64_sha256(input: &str) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(input.as_bytes()))
+}
+
+/// Checks that every disclosure is referenced by a digest in the issuer
+/// payload or in another disclosure, and returns the decoded disclosures.
+pub fn check_disclosures(
+    issuer_payload: &Map<String, Value>,
+    disclosures: &[&str],
+) -> Result<Vec<Value>, String> {
+    match issuer_payload.get("_sd_alg").and_then(Value::as_str) {
+        None | Some("sha-256") => {}
+        Some(alg) => return Err(format!("unsupported _sd_alg {:?}", alg)),
+    }
+
+    let mut digests = HashSet::new();
+    collect_digests(&Value::Object(issuer_payload.clone()), &mut digests);
+
+    let mut decoded = Vec::with_capacity(disclosures.len());
+    let mut seen = HashSet::new();
+    for disclosure in disclosures {
+        if !seen.insert(*disclosure) {
+            return Err("disclosure presented twice".to_string());
+        }
+        let bytes = general_purpose::URL_SAFE_NO_PAD
+            .decode(disclosure)
+            .map_err(|e| format!("disclosure is not base64url: {}", e))?;
+        let value: Value =
+            serde_json::from_slice(&bytes).map_err(|e| format!("disclosure is not JSON: {}", e))?;
+        match value.as_array().map(Vec::len) {
+            Some(2) | Some(3) => {}
+            _ => return Err("disclosure must be a 2- or 3-element array".to_string()),
+        }
+        collect_digests(&value, &mut digests);
+        decoded.push((b64_sha256(disclosure), value));
+    }
+
+    for (digest, _) in &decoded {
+        if !digests.contains(digest.as_str()) {
+            return Err("disclosure is not referenced by the credential".to_string());
+        }
+    }
+    Ok(decoded.into_iter().map(|(_, value)| value).collect())
+}
+
+/// Collects `_sd` entries and `{"...": digest}` array elements.
+fn collect_digests(value: &Value, digests: &mut HashSet<String>) {
+    match value {
+        Value::Object(map) => {
+            if let Some(Value::Array(sd)) = map.get("_sd") {
+                digests.extend(sd.iter().filter_map(Value::as_str).map(str::to_owned));
+            }
+            if let (1, Some(Value::String(digest))) = (map.len(), map.get("...")) {
+                digests.insert(digest.clone());
+            }
+            map.values().for_each(|v| collect_digests(v, digests));
+        }
+        Value::Array(items) => items.iter().for_each(|v| collect_digests(v, digests)),
+        _ => {}
+    }
+}
+
+/// Checks the KB-JWT header and its `sd_hash` and `aud` claims; `aud` must be
+/// exactly `audience`, this verifier's identifier. Signature, `iat` and
+/// `nonce` are checked by the caller.
+pub fn check_kb_jwt(
+    header: &JwsHeader,
+    payload: &JwtPayload,
+    presentation: &Presentation<'_>,
+    audience: &str,
+) -> Result<(), String> {
+    if header.token_type() != Some(KB_TYPE) {
+        return Err(format!("KB-JWT typ must be {}", KB_TYPE));
+    }
+    match payload.claim("sd_hash").and_then(Value::as_str) {
+        Some(sd_hash) if sd_hash == presentation.sd_hash => {}
+        Some(_) => return Err("sd_hash does not match the presentation".to_string()),
+        None => return Err("sd_hash missing in KB-JWT".to_string()),
+    }
+    match payload.audience() {
+        Some(aud) if aud == [audience] => Ok(()),
+        Some(_) => Err(format!("KB-JWT aud must be {}", audience)),
+        None => Err("aud missing in KB-JWT".to_string()),
+    }
+}
+
+/// Public keys of the credential issuers whose SD-JWTs are accepted,
+/// selected by the issuer JWT's `kid`.
+pub struct IssuerKeys {
+    keys: Vec<Jwk>,
+}
+
+impl IssuerKeys {
+    /// Loads a JWK Set of public keys, each with a `kid`.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let set: Value = serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing {}", path.display()))?;
+        let entries = set
+            .get("keys")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("{} is not a JWK Set", path.display()))?;
+        let mut keys = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let map = entry
+                .as_object()
+                .ok_or_else(|| anyhow!("JWK Set entries must be objects"))?;
+            let jwk = Jwk::from_map(map.clone())?;
+            if jwk.key_id().is_none() {
+                return Err(anyhow!("issuer keys need a kid"));
+            }
+            keys.push(jwk);
+        }
+        Ok(IssuerKeys { keys })
+    }
+
+    /// Verifies the issuer-signed JWT and returns its payload.
+    pub fn verify(&self, token: &str, allowed_algs: &[ProofAlg]) -> Result<JwtPayload, String> {
+        let header = crate::decode_jwt_header(token).map_err(|e| e.to_string())?;
+        let alg: ProofAlg = header
+            .get("alg")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "alg missing in issuer JWT header".to_string())?
+            .parse()?;
+        if !allowed_algs.contains(&alg) {
+            return Err(format!("alg {} is not accepted", alg));
+        }
+        let kid = header
+            .get("kid")
+            .and_then(Value::as_str)
+            .ok_or_else(|| "kid missing in issuer JWT header".to_string())?;
+        let jwk = self
+            .keys
+            .iter()
+            .find(|jwk| jwk.key_id() == Some(kid))
+            .ok_or_else(|| format!("unknown issuer key {:?}", kid))?;
+        alg.check_key(jwk)?;
+        let verifier = alg.verifier_from_jwk(jwk).map_err(|e| e.to_string())?;
+        let (payload, _header) =
+            jwt::decode_with_verifier(token, &*verifier).map_err(|e| e.to_string())?;
+        Ok(payload)
+    }
+}
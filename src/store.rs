@@ -0,0 +1,113 @@
+//! Storage backends for outstanding nonces.
+//!
+//! Every backend mints its own nonce values, so a backend that needs a
+//! particular encoding (or none at all) can choose it without the HTTP layer
+//! having to know.
+
+mod file;
+mod memory;
+mod sqlite;
+mod stateless;
+
+pub use file::FileNonceStore;
+pub use memory::InMemoryNonceStore;
+pub use sqlite::SqliteNonceStore;
+pub use stateless::StatelessNonceStore;
+
+use crate::threshold::ThresholdChallenge;
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Result of presenting a nonce to [`NonceStore::consume`].
+///
+/// Expiry comes first: once its TTL has elapsed, a nonce is `Expired` every
+/// time it is presented, whether or not it was consumed before, until
+/// [`NonceStore::sweep`] drops it and it becomes `Unknown`. Stores that keep
+/// no record of issued nonces go on reporting `Expired`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsumeOutcome {
+    /// The nonce was outstanding and has now been used up.
+    Consumed,
+    /// The nonce was issued by this store but its TTL has elapsed.
+    Expired,
+    /// The nonce was consumed before and has not expired yet. Stores
+    /// remember consumed nonces until they expire, so replays can be told
+    /// apart from made-up nonces.
+    Reused,
+    /// The nonce was never issued, or was swept after it expired.
+    Unknown,
+}
+
+#[async_trait]
+pub trait NonceStore: Send + Sync {
+    /// Mints a new nonce that stays valid until `expires_at`. A `challenge`
+    /// is kept with the nonce, wherever the store keeps it, so that every
+    /// verifier sharing the store sees it.
+    async fn issue(
+        &self,
+        expires_at: SystemTime,
+        challenge: Option<&ThresholdChallenge>,
+    ) -> anyhow::Result<String>;
+
+    /// The threshold challenge `nonce` was issued with, unless it has expired.
+    async fn challenge(
+        &self,
+        nonce: &str,
+        now: SystemTime,
+    ) -> anyhow::Result<Option<ThresholdChallenge>>;
+
+    /// Uses up `nonce` so it can never be presented again.
+    async fn consume(&self, nonce: &str, now: SystemTime) -> anyhow::Result<ConsumeOutcome>;
+
+    /// Drops every nonce, consumed or not, that expired before `now` and
+    /// returns how many were removed.
+    async fn sweep(&self, now: SystemTime) -> anyhow::Result<usize>;
+}
+
+/// Opens a store from a spec of the form `memory`, `sqlite:<path>`,
+/// `file:<path>` or `stateless:<secrets-path>`.
+pub fn open(spec: &str) -> anyhow::Result<Arc<dyn NonceStore>> {
+    let store: Arc<dyn NonceStore> = match spec.split_once(':') {
+        None if spec == "memory" => Arc::new(InMemoryNonceStore::new()),
+        Some(("sqlite", path)) => Arc::new(SqliteNonceStore::open(path)?),
+        Some(("file", path)) => Arc::new(FileNonceStore::open(path)?),
+        Some(("stateless", path)) => Arc::new(StatelessNonceStore::from_file(path)?),
+        _ => anyhow::bail!("unknown nonce store {:?}", spec),
+    };
+    Ok(store)
+}
+
+/// Checks a store spec without opening the store, so nothing is created: the
+/// backend must be known, a `sqlite:` or `file:` path must be in an existing
+/// directory, and `stateless:` secrets must load.
+pub fn check(spec: &str) -> anyhow::Result<()> {
+    match spec.split_once(':') {
+        None if spec == "memory" => Ok(()),
+        Some(("sqlite" | "file", path)) => {
+            let dir = match Path::new(path).parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            if !dir.is_dir() {
+                anyhow::bail!("directory {} does not exist", dir.display());
+            }
+            Ok(())
+        }
+        Some(("stateless", path)) => StatelessNonceStore::from_file(path).map(drop),
+        _ => anyhow::bail!("unknown nonce store {:?}", spec),
+    }
+}
+
+fn new_nonce() -> String {
+    Uuid::new_v4().to_string()
+}
+
+fn unix_millis(t: SystemTime) -> i64 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i64,
+        Err(_) => 0,
+    }
+}
@@ -0,0 +1,196 @@
+use super::{new_nonce, unix_millis, ConsumeOutcome, NonceStore};
+use crate::threshold::ThresholdChallenge;
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Keeps nonces in an append-only log file.
+///
+/// Each line is either `I <nonce> <expires_at_ms> [<challenge>]` for an
+/// issued nonce, the challenge being [`ThresholdChallenge::encode`]d, or
+/// `C <nonce>` for a consumed one. The log is replayed on open, and `sweep`
+/// compacts it down to the nonces that have not expired. The file is
+/// owned by a single process; use the SQLite store to share state.
+pub struct FileNonceStore {
+    inner: Arc<Mutex<FileLog>>,
+}
+
+struct FileLog {
+    path: PathBuf,
+    file: File,
+    /// Unexpired nonces.
+    live: HashMap<String, Entry>,
+}
+
+struct Entry {
+    /// Unix milliseconds.
+    expires_at: i64,
+    consumed: bool,
+    challenge: Option<ThresholdChallenge>,
+}
+
+impl Entry {
+    fn issued_line(&self, nonce: &str) -> String {
+        match &self.challenge {
+            Some(challenge) => format!("I {} {} {}", nonce, self.expires_at, challenge.encode()),
+            None => format!("I {} {}", nonce, self.expires_at),
+        }
+    }
+}
+
+impl FileNonceStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut live = HashMap::new();
+        if path.exists() {
+            let reader = BufReader::new(File::open(&path)?);
+            for (lineno, line) in reader.lines().enumerate() {
+                let line = line?;
+                replay(&mut live, &line)
+                    .with_context(|| format!("{}:{}", path.display(), lineno + 1))?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(FileNonceStore {
+            inner: Arc::new(Mutex::new(FileLog { path, file, live })),
+        })
+    }
+
+    /// Runs `f` against the log on the blocking thread pool.
+    async fn with_log<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut FileLog) -> anyhow::Result<T> + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || f(&mut inner.lock().unwrap())).await?
+    }
+}
+
+fn replay(live: &mut HashMap<String, Entry>, line: &str) -> anyhow::Result<()> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    match fields.as_slice() {
+        ["I", nonce, expires_at, challenge @ ..] if challenge.len() <= 1 => {
+            let entry = Entry {
+                expires_at: expires_at.parse()?,
+                consumed: false,
+                challenge: match challenge {
+                    [challenge] => Some(ThresholdChallenge::decode(challenge)?),
+                    _ => None,
+                },
+            };
+            live.insert((*nonce).to_owned(), entry);
+        }
+        ["C", nonce] => {
+            if let Some(entry) = live.get_mut(*nonce) {
+                entry.consumed = true;
+            }
+        }
+        [] => {}
+        _ => return Err(anyhow!("malformed nonce log entry")),
+    }
+    Ok(())
+}
+
+impl FileLog {
+    fn append(&mut self, entry: &str) -> anyhow::Result<()> {
+        writeln!(self.file, "{}", entry)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Rewrites the log so it only contains the unexpired nonces.
+    fn compact(&mut self) -> anyhow::Result<()> {
+        let tmp_path = self.path.with_extension("compact");
+        let mut tmp = File::create(&tmp_path)?;
+        for (nonce, entry) in &self.live {
+            writeln!(tmp, "{}", entry.issued_line(nonce))?;
+            if entry.consumed {
+                writeln!(tmp, "C {}", nonce)?;
+            }
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl NonceStore for FileNonceStore {
+    async fn issue(
+        &self,
+        expires_at: SystemTime,
+        challenge: Option<&ThresholdChallenge>,
+    ) -> anyhow::Result<String> {
+        let nonce = new_nonce();
+        let value = nonce.clone();
+        let entry = Entry {
+            expires_at: unix_millis(expires_at),
+            consumed: false,
+            challenge: challenge.cloned(),
+        };
+        self.with_log(move |log| {
+            log.append(&entry.issued_line(&value))?;
+            log.live.insert(value, entry);
+            Ok(())
+        })
+        .await?;
+        Ok(nonce)
+    }
+
+    async fn challenge(
+        &self,
+        nonce: &str,
+        now: SystemTime,
+    ) -> anyhow::Result<Option<ThresholdChallenge>> {
+        let nonce = nonce.to_owned();
+        let now = unix_millis(now);
+        self.with_log(move |log| {
+            Ok(log
+                .live
+                .get(&nonce)
+                .filter(|entry| now < entry.expires_at)
+                .and_then(|entry| entry.challenge.clone()))
+        })
+        .await
+    }
+
+    async fn consume(&self, nonce: &str, now: SystemTime) -> anyhow::Result<ConsumeOutcome> {
+        let nonce = nonce.to_owned();
+        let now = unix_millis(now);
+        self.with_log(move |log| {
+            match log.live.get(&nonce) {
+                Some(entry) if now >= entry.expires_at => return Ok(ConsumeOutcome::Expired),
+                Some(Entry { consumed: true, .. }) => return Ok(ConsumeOutcome::Reused),
+                Some(_) => {}
+                None => return Ok(ConsumeOutcome::Unknown),
+            }
+            log.append(&format!("C {}", nonce))?;
+            if let Some(entry) = log.live.get_mut(&nonce) {
+                entry.consumed = true;
+            }
+            Ok(ConsumeOutcome::Consumed)
+        })
+        .await
+    }
+
+    async fn sweep(&self, now: SystemTime) -> anyhow::Result<usize> {
+        let now = unix_millis(now);
+        self.with_log(move |log| {
+            let before = log.live.len();
+            log.live.retain(|_, entry| now < entry.expires_at);
+            let removed = before - log.live.len();
+            if removed > 0 {
+                log.compact()?;
+            }
+            Ok(removed)
+        })
+        .await
+    }
+}
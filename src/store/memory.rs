@@ -0,0 +1,79 @@
+use super::{new_nonce, ConsumeOutcome, NonceStore};
+use crate::threshold::ThresholdChallenge;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Keeps nonces in process memory. Outstanding challenges are lost on restart.
+#[derive(Default)]
+pub struct InMemoryNonceStore {
+    nonces: Mutex<HashMap<String, Entry>>,
+}
+
+/// An issued nonce.
+struct Entry {
+    expires_at: SystemTime,
+    consumed: bool,
+    challenge: Option<ThresholdChallenge>,
+}
+
+impl InMemoryNonceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl NonceStore for InMemoryNonceStore {
+    async fn issue(
+        &self,
+        expires_at: SystemTime,
+        challenge: Option<&ThresholdChallenge>,
+    ) -> anyhow::Result<String> {
+        let nonce = new_nonce();
+        self.nonces.lock().unwrap().insert(
+            nonce.clone(),
+            Entry {
+                expires_at,
+                consumed: false,
+                challenge: challenge.cloned(),
+            },
+        );
+        Ok(nonce)
+    }
+
+    async fn challenge(
+        &self,
+        nonce: &str,
+        now: SystemTime,
+    ) -> anyhow::Result<Option<ThresholdChallenge>> {
+        Ok(self
+            .nonces
+            .lock()
+            .unwrap()
+            .get(nonce)
+            .filter(|entry| now < entry.expires_at)
+            .and_then(|entry| entry.challenge.clone()))
+    }
+
+    async fn consume(&self, nonce: &str, now: SystemTime) -> anyhow::Result<ConsumeOutcome> {
+        let mut nonces = self.nonces.lock().unwrap();
+        Ok(match nonces.get_mut(nonce) {
+            Some(entry) if now >= entry.expires_at => ConsumeOutcome::Expired,
+            Some(Entry { consumed: true, .. }) => ConsumeOutcome::Reused,
+            Some(entry) => {
+                entry.consumed = true;
+                ConsumeOutcome::Consumed
+            }
+            None => ConsumeOutcome::Unknown,
+        })
+    }
+
+    async fn sweep(&self, now: SystemTime) -> anyhow::Result<usize> {
+        let mut nonces = self.nonces.lock().unwrap();
+        let before = nonces.len();
+        nonces.retain(|_, entry| now < entry.expires_at);
+        Ok(before - nonces.len())
+    }
+}
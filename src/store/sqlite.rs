@@ -0,0 +1,144 @@
+use super::{new_nonce, unix_millis, ConsumeOutcome, NonceStore};
+use crate::threshold::ThresholdChallenge;
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Keeps nonces in an embedded SQLite database.
+///
+/// The database runs in WAL mode, so several verifier processes on the same
+/// host can share one file.
+pub struct SqliteNonceStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteNonceStore {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS nonces (
+                nonce TEXT PRIMARY KEY,
+                expires_at INTEGER NOT NULL,
+                consumed INTEGER NOT NULL DEFAULT 0,
+                challenge TEXT
+            )",
+            [],
+        )?;
+        // Databases created before consumed nonces were kept, or before
+        // threshold challenges were stored with their nonce, lack the columns.
+        for (column, definition) in [
+            ("consumed", "INTEGER NOT NULL DEFAULT 0"),
+            ("challenge", "TEXT"),
+        ] {
+            let exists: bool = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('nonces') WHERE name = ?1",
+                params![column],
+                |row| row.get::<_, i64>(0).map(|n| n > 0),
+            )?;
+            if !exists {
+                conn.execute(
+                    &format!("ALTER TABLE nonces ADD COLUMN {} {}", column, definition),
+                    [],
+                )?;
+            }
+        }
+        Ok(SqliteNonceStore {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Runs `f` against the connection on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let result = tokio::task::spawn_blocking(move || f(&conn.lock().unwrap())).await?;
+        Ok(result?)
+    }
+}
+
+#[async_trait]
+impl NonceStore for SqliteNonceStore {
+    async fn issue(
+        &self,
+        expires_at: SystemTime,
+        challenge: Option<&ThresholdChallenge>,
+    ) -> anyhow::Result<String> {
+        let nonce = new_nonce();
+        let value = nonce.clone();
+        let challenge = challenge.map(serde_json::to_string).transpose()?;
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO nonces (nonce, expires_at, challenge) VALUES (?1, ?2, ?3)",
+                params![value, unix_millis(expires_at), challenge],
+            )
+        })
+        .await?;
+        Ok(nonce)
+    }
+
+    async fn challenge(
+        &self,
+        nonce: &str,
+        now: SystemTime,
+    ) -> anyhow::Result<Option<ThresholdChallenge>> {
+        let nonce = nonce.to_owned();
+        let now = unix_millis(now);
+        let challenge = self
+            .with_conn(move |conn| {
+                conn.query_row(
+                    "SELECT challenge FROM nonces WHERE nonce = ?1 AND expires_at > ?2",
+                    params![nonce, now],
+                    |row| row.get::<_, Option<String>>(0),
+                )
+                .optional()
+            })
+            .await?;
+        match challenge.flatten() {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn consume(&self, nonce: &str, now: SystemTime) -> anyhow::Result<ConsumeOutcome> {
+        let nonce = nonce.to_owned();
+        let now = unix_millis(now);
+        self.with_conn(move |conn| {
+            let consumed = conn.execute(
+                "UPDATE nonces SET consumed = 1
+                 WHERE nonce = ?1 AND consumed = 0 AND expires_at > ?2",
+                params![nonce, now],
+            )?;
+            if consumed > 0 {
+                return Ok(ConsumeOutcome::Consumed);
+            }
+            let known = conn
+                .query_row(
+                    "SELECT expires_at FROM nonces WHERE nonce = ?1",
+                    params![nonce],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?;
+            Ok(match known {
+                Some(expires_at) if now >= expires_at => ConsumeOutcome::Expired,
+                Some(_) => ConsumeOutcome::Reused,
+                None => ConsumeOutcome::Unknown,
+            })
+        })
+        .await
+    }
+
+    async fn sweep(&self, now: SystemTime) -> anyhow::Result<usize> {
+        let now = unix_millis(now);
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM nonces WHERE expires_at <= ?1", params![now])
+        })
+        .await
+    }
+}
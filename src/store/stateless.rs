@@ -0,0 +1,164 @@
+use super::{ConsumeOutcome, NonceStore};
+use crate::threshold::ThresholdChallenge;
+use anyhow::anyhow;
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const VERSION: &str = "v1";
+
+/// Issues self-authenticating nonces instead of remembering them.
+///
+/// A nonce has the form `v1.<random>.<iat>.<exp>.<mac>`, where the MAC is an
+/// HMAC-SHA256 over everything before it. A threshold challenge travels in
+/// the nonce itself, [`ThresholdChallenge::encode`]d between `<exp>` and the
+/// MAC. Verifiers sharing the secret can check any nonce without shared
+/// state; only nonces that have already been consumed are remembered, and
+/// only until they would have expired anyway.
+///
+/// That replay cache lives in the memory of this process. Instances sharing
+/// the secrets do not see each other's caches, so a nonce can be consumed
+/// once at each of them, and again after a restart; use a shared store where
+/// nonces must be single-use across instances. `<exp>` is in whole Unix
+/// seconds, rounded down, so a nonce may expire up to a second early.
+pub struct StatelessNonceStore {
+    /// The first secret signs new nonces; all of them are accepted, so a new
+    /// secret can be rolled out before the old one is retired.
+    secrets: Vec<Vec<u8>>,
+    /// Consumed nonces mapped to their expiry.
+    replay_cache: Mutex<HashMap<String, SystemTime>>,
+}
+
+impl StatelessNonceStore {
+    pub fn new(secrets: Vec<Vec<u8>>) -> anyhow::Result<Self> {
+        if secrets.is_empty() {
+            return Err(anyhow!("stateless nonces need at least one secret"));
+        }
+        if secrets.iter().any(|s| s.len() < 32) {
+            return Err(anyhow!("stateless nonce secrets must be at least 32 bytes"));
+        }
+        Ok(StatelessNonceStore {
+            secrets,
+            replay_cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Loads secrets from a file holding one base64url-encoded secret per line.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let secrets = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| general_purpose::URL_SAFE_NO_PAD.decode(line.trim_end_matches('=')))
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(secrets)
+    }
+
+    fn mac(secret: &[u8], message: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts any key length");
+        mac.update(message.as_bytes());
+        mac
+    }
+
+    /// Checks the MAC and returns the expiry and challenge encoded in `nonce`.
+    fn open(&self, nonce: &str) -> Option<(SystemTime, Option<ThresholdChallenge>)> {
+        let (message, tag) = nonce.rsplit_once('.')?;
+        let tag = general_purpose::URL_SAFE_NO_PAD.decode(tag).ok()?;
+        if !self
+            .secrets
+            .iter()
+            .any(|secret| Self::mac(secret, message).verify_slice(&tag).is_ok())
+        {
+            return None;
+        }
+        let fields: Vec<&str> = message.split('.').collect();
+        let (exp, challenge) = match fields.as_slice() {
+            [VERSION, _random, _iat, exp] => (exp, None),
+            [VERSION, _random, _iat, exp, challenge] => {
+                (exp, Some(ThresholdChallenge::decode(challenge).ok()?))
+            }
+            _ => return None,
+        };
+        let exp = exp.parse::<u64>().ok()?;
+        Some((UNIX_EPOCH + Duration::from_secs(exp), challenge))
+    }
+}
+
+fn unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[async_trait]
+impl NonceStore for StatelessNonceStore {
+    async fn issue(
+        &self,
+        expires_at: SystemTime,
+        challenge: Option<&ThresholdChallenge>,
+    ) -> anyhow::Result<String> {
+        let random = general_purpose::URL_SAFE_NO_PAD.encode(Uuid::new_v4().as_bytes());
+        let mut message = format!(
+            "{}.{}.{}.{}",
+            VERSION,
+            random,
+            unix_secs(SystemTime::now()),
+            unix_secs(expires_at)
+        );
+        if let Some(challenge) = challenge {
+            message = format!("{}.{}", message, challenge.encode());
+        }
+        let tag = Self::mac(&self.secrets[0], &message)
+            .finalize()
+            .into_bytes();
+        Ok(format!(
+            "{}.{}",
+            message,
+            general_purpose::URL_SAFE_NO_PAD.encode(tag)
+        ))
+    }
+
+    async fn challenge(
+        &self,
+        nonce: &str,
+        now: SystemTime,
+    ) -> anyhow::Result<Option<ThresholdChallenge>> {
+        Ok(self
+            .open(nonce)
+            .filter(|(expires_at, _)| now < *expires_at)
+            .and_then(|(_, challenge)| challenge))
+    }
+
+    async fn consume(&self, nonce: &str, now: SystemTime) -> anyhow::Result<ConsumeOutcome> {
+        let expires_at = match self.open(nonce) {
+            Some((expires_at, _)) => expires_at,
+            None => return Ok(ConsumeOutcome::Unknown),
+        };
+        if now >= expires_at {
+            return Ok(ConsumeOutcome::Expired);
+        }
+        let mut replay_cache = self.replay_cache.lock().unwrap();
+        if replay_cache.contains_key(nonce) {
+            return Ok(ConsumeOutcome::Reused);
+        }
+        replay_cache.insert(nonce.to_owned(), expires_at);
+        Ok(ConsumeOutcome::Consumed)
+    }
+
+    async fn sweep(&self, now: SystemTime) -> anyhow::Result<usize> {
+        let mut replay_cache = self.replay_cache.lock().unwrap();
+        let before = replay_cache.len();
+        replay_cache.retain(|_, expires_at| now < *expires_at);
+        Ok(before - replay_cache.len())
+    }
+}
@@ -0,0 +1,59 @@
+//! k-of-n challenges: a nonce that is only answered by proofs from at least
+//! `threshold` of a given set of registered keys.
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+/// Which registered keys must answer a nonce, and how many of them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ThresholdChallenge {
+    pub kids: Vec<String>,
+    pub threshold: usize,
+}
+
+impl ThresholdChallenge {
+    pub fn new(kids: Vec<String>, threshold: usize) -> Result<Self, String> {
+        if kids.is_empty() {
+            return Err("kids must not be empty".to_string());
+        }
+        let mut seen = HashSet::new();
+        if let Some(kid) = kids.iter().find(|kid| !seen.insert(kid.as_str())) {
+            return Err(format!("kid {:?} is listed twice", kid));
+        }
+        if threshold == 0 || threshold > kids.len() {
+            return Err(format!(
+                "threshold must be between 1 and {}, got {}",
+                kids.len(),
+                threshold
+            ));
+        }
+        Ok(ThresholdChallenge { kids, threshold })
+    }
+
+    /// Returns true if `kid` is one of the keys that may answer.
+    pub fn includes(&self, kid: &str) -> bool {
+        self.kids.iter().any(|k| k == kid)
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "type": "threshold",
+            "kids": self.kids,
+            "threshold": self.threshold,
+        })
+    }
+
+    /// base64url JSON, for stores that keep the challenge in a line of text
+    /// or in the nonce itself.
+    pub fn encode(&self) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(json!(self).to_string())
+    }
+
+    pub fn decode(encoded: &str) -> anyhow::Result<Self> {
+        let json = general_purpose::URL_SAFE_NO_PAD.decode(encoded)?;
+        let challenge: ThresholdChallenge = serde_json::from_slice(&json)?;
+        ThresholdChallenge::new(challenge.kids, challenge.threshold).map_err(anyhow::Error::msg)
+    }
+}
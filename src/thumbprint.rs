@@ -0,0 +1,38 @@
+//! RFC 7638 JWK thumbprints.
+
+use base64::{engine::general_purpose, Engine as _};
+use josekit::jwk::Jwk;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Members that make up the thumbprint input for each key type, in
+/// lexicographic order.
+fn required_members(kty: &str) -> Option<&'static [&'static str]> {
+    match kty {
+        "EC" => Some(&["crv", "kty", "x", "y"]),
+        "OKP" => Some(&["crv", "kty", "x"]),
+        "RSA" => Some(&["e", "kty", "n"]),
+        "oct" => Some(&["k", "kty"]),
+        _ => None,
+    }
+}
+
+/// Computes the base64url-encoded SHA-256 thumbprint of `jwk`.
+pub fn sha256_thumbprint(jwk: &Jwk) -> Result<String, String> {
+    let members = required_members(jwk.key_type())
+        .ok_or_else(|| format!("no thumbprint defined for kty {:?}", jwk.key_type()))?;
+    let mut fields = Vec::with_capacity(members.len());
+    for name in members {
+        let value = match jwk.parameter(name) {
+            Some(Value::String(value)) => value,
+            _ => return Err(format!("JWK member {} is missing", name)),
+        };
+        fields.push(format!(
+            "{}:{}",
+            Value::from(*name),
+            Value::from(value.as_str())
+        ));
+    }
+    let canonical = format!("{{{}}}", fields.join(","));
+    Ok(general_purpose::URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes())))
+}
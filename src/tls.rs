@@ -0,0 +1,171 @@
+//! TLS termination with rustls: a server certificate that is reloaded when
+//! its files change, and optional client certificates.
+
+use crate::config::{ClientAuth, TlsConfig};
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use anyhow::{anyhow, Context};
+use josekit::jwk::Jwk;
+use key_ownership_prover::{thumbprint, x5c};
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use std::any::Any;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// The server certificate and key, swapped in place when their files change.
+pub struct ReloadingCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    state: RwLock<CertState>,
+}
+
+struct CertState {
+    key: Arc<CertifiedKey>,
+    modified: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl fmt::Debug for ReloadingCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadingCert")
+            .field("cert_path", &self.cert_path)
+            .field("key_path", &self.key_path)
+            .finish()
+    }
+}
+
+impl ReloadingCert {
+    pub fn load(cert_path: &Path, key_path: &Path) -> anyhow::Result<Self> {
+        let modified = modified(cert_path, key_path);
+        let key = load_certified_key(cert_path, key_path)?;
+        Ok(ReloadingCert {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            state: RwLock::new(CertState {
+                key: Arc::new(key),
+                modified,
+            }),
+        })
+    }
+
+    /// Reloads the certificate if either file's modification time changed.
+    /// A pair that fails to load leaves the current one in place, so a
+    /// half-finished renewal does not take the server down.
+    pub fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let modified = modified(&self.cert_path, &self.key_path);
+        if modified == self.state.read().unwrap().modified {
+            return Ok(false);
+        }
+        let key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.state.write().unwrap() = CertState {
+            key: Arc::new(key),
+            modified,
+        };
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.state.read().unwrap().key.clone())
+    }
+}
+
+fn modified(cert_path: &Path, key_path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    (modified(cert_path), modified(key_path))
+}
+
+fn load_certs(path: &Path) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let mut reader =
+        BufReader::new(File::open(path).with_context(|| format!("reading {}", path.display()))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("parsing {}", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("{} contains no certificates", path.display()));
+    }
+    Ok(certs)
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> anyhow::Result<CertifiedKey> {
+    let certs = load_certs(cert_path)?;
+    let mut reader = BufReader::new(
+        File::open(key_path).with_context(|| format!("reading {}", key_path.display()))?,
+    );
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut reader)
+        .with_context(|| format!("parsing {}", key_path.display()))?
+        .ok_or_else(|| anyhow!("{} contains no private key", key_path.display()))?;
+    let signing_key = ring::sign::any_supported_type(&key)
+        .map_err(|e| anyhow!("unusable private key in {}: {}", key_path.display(), e))?;
+    let certified = CertifiedKey::new(certs, signing_key);
+    certified.keys_match().map_err(|e| {
+        anyhow!(
+            "{} does not match {}: {}",
+            key_path.display(),
+            cert_path.display(),
+            e
+        )
+    })?;
+    Ok(certified)
+}
+
+/// Builds the rustls configuration for `tls`, returning the certificate
+/// resolver so that it can be reloaded.
+pub fn server_config(tls: &TlsConfig) -> anyhow::Result<(ServerConfig, Arc<ReloadingCert>)> {
+    let provider = Arc::new(ring::default_provider());
+    let cert = Arc::new(ReloadingCert::load(&tls.cert, &tls.key)?);
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &tls.client_auth {
+        ClientAuth::None => builder.with_no_client_auth(),
+        ClientAuth::Optional(ca) | ClientAuth::Required(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("invalid client CA in {}", ca.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match &tls.client_auth {
+                ClientAuth::Optional(_) => verifier.allow_unauthenticated(),
+                _ => verifier,
+            };
+            builder.with_client_cert_verifier(verifier.build()?)
+        }
+    };
+    let config = builder.with_cert_resolver(cert.clone());
+    Ok((config, cert))
+}
+
+/// The key of the certificate a client authenticated with.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    /// RFC 7638 thumbprint of the certificate's public key, or why it could
+    /// not be computed.
+    pub jkt: Result<String, String>,
+}
+
+/// Records the client certificate of a TLS connection, if any, for
+/// `HttpRequest::conn_data`.
+pub fn on_connect(conn: &dyn Any, ext: &mut Extensions) {
+    let tls = match conn.downcast_ref::<TlsStream<TcpStream>>() {
+        Some(tls) => tls,
+        None => return,
+    };
+    let (_, session) = tls.get_ref();
+    if let Some(leaf) = session.peer_certificates().and_then(|certs| certs.first()) {
+        let jkt = x5c::certificate_jwk(leaf.as_ref())
+            .and_then(|jwk| Jwk::from_map(jwk).map_err(|e| e.to_string()))
+            .and_then(|jwk| thumbprint::sha256_thumbprint(&jwk));
+        ext.insert(ClientCertificate { jkt });
+    }
+}
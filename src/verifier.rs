@@ -0,0 +1,841 @@
+//! Framework-independent proof verification: key resolution, signature,
+//! claim and nonce checks.
+
+use crate::algs::ProofAlg;
+use crate::claims::{self, BindingPolicy, TimePolicy};
+use crate::did;
+use crate::dpop::{self, DpopError, DpopRequest, JtiCache};
+use crate::jwk_policy::{self, JwkPolicyError};
+use crate::jws_json::{self, Signature};
+use crate::openid4vci::{self, Oid4vciError, Oid4vciPolicy};
+use crate::registry::{KeyRegistry, RegisteredKey, RotationError};
+use crate::remote_keys::{FetchError, RemoteKeys};
+use crate::sd_jwt::{self, IssuerKeys, PresentationError, VerifiedPresentation};
+use crate::store::{ConsumeOutcome, NonceStore};
+use crate::threshold::ThresholdChallenge;
+use crate::thumbprint;
+use crate::x5c::{self, CertifiedKey, TrustAnchors};
+use anyhow::anyhow;
+use base64::{engine::general_purpose, Engine as _};
+use josekit::{
+    jwk::Jwk,
+    jws::JwsHeader,
+    jwt::{self, JwtPayload},
+    JoseError,
+};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// What a proof must satisfy beyond a valid signature.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    pub allowed_algs: Vec<ProofAlg>,
+    pub time: TimePolicy,
+    pub binding: BindingPolicy,
+    /// RFC 7638 thumbprint the proof's key must have, e.g. that of the TLS
+    /// client certificate the request arrived with.
+    pub bound_jkt: Option<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            allowed_algs: ProofAlg::ALL.to_vec(),
+            time: TimePolicy::default(),
+            binding: BindingPolicy::default(),
+            bound_jkt: None,
+        }
+    }
+}
+
+/// A proof whose signature has been checked against the key in its header.
+pub struct VerifiedProof {
+    pub jwk: Jwk,
+    pub header: JwsHeader,
+    pub payload: JwtPayload,
+    /// RFC 7638 SHA-256 thumbprint of `jwk`.
+    pub thumbprint: String,
+    /// The registry entry the key was resolved from, if the header named a
+    /// `kid` instead of embedding a JWK.
+    pub enrolled: Option<RegisteredKey>,
+    /// The validated `x5c` chain the key was taken from.
+    pub certificate: Option<CertifiedKey>,
+    /// The `did:key` or `did:jwk` DID the key was resolved from.
+    pub did: Option<String>,
+}
+
+impl VerifiedProof {
+    /// The `nonce` claim, if it is a string.
+    pub fn nonce(&self) -> Option<&str> {
+        self.payload.claim("nonce").and_then(Value::as_str)
+    }
+}
+
+#[derive(Debug)]
+pub enum ProofError {
+    /// The token or its header cannot be parsed.
+    Malformed(String),
+    /// `alg` is missing, unknown or not accepted by the policy.
+    UnsupportedAlg(String),
+    /// The header does not lead to a usable key.
+    InvalidKey(String),
+    /// The key breaks the public JWK policy.
+    KeyPolicy(JwkPolicyError),
+    /// A `jku` or `x5u` document could not be fetched.
+    RemoteKey(FetchError),
+    /// An `x5c` or `x5u` chain failed validation.
+    Certificate(String),
+    /// The signature does not verify with the resolved key.
+    BadSignature(String),
+    /// The proof's key is not the one [`Policy::bound_jkt`] requires.
+    BoundKeyMismatch,
+    /// A time or binding claim check failed.
+    InvalidClaims(String),
+    /// The payload has no `nonce`.
+    NonceMissing,
+    NonceExpired,
+    /// The nonce was never issued by this verifier.
+    NonceUnknown,
+    /// The nonce has already been consumed by an earlier proof.
+    NonceReused,
+    /// The nonce belongs to a threshold challenge, which a single proof
+    /// cannot answer.
+    ThresholdRequired,
+    /// Too few of a threshold challenge's keys signed.
+    ThresholdNotMet {
+        met: usize,
+        threshold: usize,
+    },
+    /// The signatures of one request answer different nonces.
+    NonceMismatch,
+    /// None of the signatures of a multi-signature proof verified.
+    NoSignatureVerified,
+    /// The nonce store failed.
+    NonceStore(anyhow::Error),
+}
+
+impl ProofError {
+    /// Stable machine-readable code for the failure. Codes are part of the
+    /// API: new ones may be added, existing ones are never renamed.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProofError::Malformed(_) => "malformed_token",
+            ProofError::UnsupportedAlg(_) => "unsupported_alg",
+            ProofError::InvalidKey(_) => "invalid_key",
+            ProofError::KeyPolicy(e) => e.code(),
+            ProofError::RemoteKey(e) => e.code(),
+            ProofError::Certificate(_) => "invalid_certificate",
+            ProofError::BadSignature(_) => "bad_signature",
+            ProofError::BoundKeyMismatch => "bound_key_mismatch",
+            ProofError::InvalidClaims(_) => "invalid_claims",
+            ProofError::NonceMissing => "nonce_missing",
+            ProofError::NonceExpired => "nonce_expired",
+            ProofError::NonceUnknown => "nonce_unknown",
+            ProofError::NonceReused => "nonce_reused",
+            ProofError::ThresholdRequired => "threshold_required",
+            ProofError::ThresholdNotMet { .. } => "threshold_not_met",
+            ProofError::NonceMismatch => "nonce_mismatch",
+            ProofError::NoSignatureVerified => "no_signature_verified",
+            ProofError::NonceStore(_) => "nonce_store_unavailable",
+        }
+    }
+
+    /// Short human-readable summary of the failure class, the same for every
+    /// occurrence of a code.
+    pub fn title(&self) -> &'static str {
+        match self {
+            ProofError::Malformed(_) => "Malformed proof",
+            ProofError::UnsupportedAlg(_) => "Unsupported algorithm",
+            ProofError::InvalidKey(_) => "Unusable key reference",
+            ProofError::KeyPolicy(_) => "Key rejected by policy",
+            ProofError::RemoteKey(_) => "Remote key unavailable",
+            ProofError::Certificate(_) => "Invalid certificate chain",
+            ProofError::BadSignature(_) => "Signature verification failed",
+            ProofError::BoundKeyMismatch => "Key does not match the request's binding",
+            ProofError::InvalidClaims(_) => "Invalid claims",
+            ProofError::NonceMissing => "Nonce missing",
+            ProofError::NonceExpired => "Nonce expired",
+            ProofError::NonceUnknown => "Unknown nonce",
+            ProofError::NonceReused => "Nonce already used",
+            ProofError::ThresholdRequired => "Threshold proof required",
+            ProofError::ThresholdNotMet { .. } => "Threshold not met",
+            ProofError::NonceMismatch => "Signatures answer different nonces",
+            ProofError::NoSignatureVerified => "No signature verified",
+            ProofError::NonceStore(_) => "Nonce store unavailable",
+        }
+    }
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofError::Malformed(msg)
+            | ProofError::UnsupportedAlg(msg)
+            | ProofError::InvalidKey(msg)
+            | ProofError::Certificate(msg)
+            | ProofError::BadSignature(msg)
+            | ProofError::InvalidClaims(msg) => write!(f, "{}", msg),
+            ProofError::KeyPolicy(e) => write!(f, "{}", e),
+            ProofError::RemoteKey(e) => write!(f, "{}", e),
+            ProofError::BoundKeyMismatch => {
+                write!(f, "proof key does not match the key bound to this request")
+            }
+            ProofError::NonceMissing => write!(f, "nonce not found in claims"),
+            ProofError::NonceExpired => write!(f, "nonce expired"),
+            ProofError::NonceUnknown => write!(f, "nonce was not issued by this verifier"),
+            ProofError::NonceReused => write!(f, "nonce has already been used"),
+            ProofError::ThresholdRequired => {
+                write!(f, "nonce must be answered by a threshold proof")
+            }
+            ProofError::ThresholdNotMet { met, threshold } => {
+                write!(f, "{} of the required {} keys signed", met, threshold)
+            }
+            ProofError::NonceMismatch => write!(f, "signatures answer different nonces"),
+            ProofError::NoSignatureVerified => write!(f, "no signature verified"),
+            ProofError::NonceStore(_) => write!(f, "nonce store unavailable"),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProofError::KeyPolicy(e) => Some(e),
+            ProofError::RemoteKey(e) => Some(e),
+            ProofError::NonceStore(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// The outcome of a multi-signature proof, one entry per signature.
+pub struct MultiProof {
+    pub signatures: Vec<Result<VerifiedProof, ProofError>>,
+    /// The threshold challenge the nonce answered, if any.
+    pub challenge: Option<ThresholdChallenge>,
+    /// With a challenge, whether each signature counted towards it.
+    pub counted: Option<Vec<bool>>,
+}
+
+impl MultiProof {
+    pub fn verified(&self) -> impl Iterator<Item = &VerifiedProof> {
+        self.signatures.iter().filter_map(|r| r.as_ref().ok())
+    }
+}
+
+/// A failed multi-signature proof, with what was learned about each signature.
+pub struct MultiProofError {
+    pub error: ProofError,
+    pub proof: MultiProof,
+}
+
+/// Verifies proofs against the keys, trust anchors and nonces it is given.
+pub struct Verifier {
+    /// Issues and consumes nonces.
+    pub nonces: Arc<dyn NonceStore>,
+    /// Keys proofs can name by `kid`.
+    pub registry: Arc<KeyRegistry>,
+    /// CA certificates `x5c` chains must lead to; `x5c` is refused without them.
+    pub trust_anchors: Option<TrustAnchors>,
+    /// Fetches `jku` and `x5u` keys; both are refused when unset.
+    pub remote_keys: Option<RemoteKeys>,
+}
+
+impl Verifier {
+    /// A verifier with an empty in-memory registry and no `x5c`, `x5u` or
+    /// `jku` support.
+    pub fn new(nonces: Arc<dyn NonceStore>) -> Self {
+        Verifier {
+            nonces,
+            registry: Arc::new(KeyRegistry::new()),
+            trust_anchors: None,
+            remote_keys: None,
+        }
+    }
+
+    /// Issues a nonce valid until `expires_at`, optionally as a threshold
+    /// challenge.
+    pub async fn issue_nonce(
+        &self,
+        expires_at: SystemTime,
+        challenge: Option<ThresholdChallenge>,
+    ) -> anyhow::Result<String> {
+        self.nonces.issue(expires_at, challenge.as_ref()).await
+    }
+
+    /// Verifies a compact proof: its signature, time and binding claims, and
+    /// its nonce, which is consumed.
+    pub async fn verify(&self, token: &str, policy: &Policy) -> Result<VerifiedProof, ProofError> {
+        let proof = self.verify_signature(token, policy).await?;
+        self.check_claims(&proof.payload, policy)?;
+        let nonce = proof.nonce().ok_or(ProofError::NonceMissing)?;
+        self.consume_nonce(nonce).await?;
+        Ok(proof)
+    }
+
+    /// Verifies several signatures answering one nonce: a JWS in JSON
+    /// Serialization, or a bundle of compact JWSs. The nonce is consumed once,
+    /// when at least one signature verified or, for a threshold challenge,
+    /// when enough of its keys did.
+    pub async fn verify_all(
+        &self,
+        signatures: &[Signature],
+        policy: &Policy,
+    ) -> Result<MultiProof, MultiProofError> {
+        if signatures.len() > jws_json::MAX_SIGNATURES {
+            return Err(MultiProofError {
+                error: ProofError::Malformed(format!(
+                    "at most {} signatures are accepted",
+                    jws_json::MAX_SIGNATURES
+                )),
+                proof: MultiProof {
+                    signatures: Vec::new(),
+                    challenge: None,
+                    counted: None,
+                },
+            });
+        }
+        let mut results = Vec::with_capacity(signatures.len());
+        for signature in signatures {
+            let result = match self
+                .verify_signature_with_header(&signature.token, &signature.header, policy)
+                .await
+            {
+                Ok(proof) => {
+                    self.check_claims(&proof.payload, policy)
+                        .and_then(|()| match proof.nonce() {
+                            Some(_) => Ok(proof),
+                            None => Err(ProofError::NonceMissing),
+                        })
+                }
+                Err(e) => Err(e),
+            };
+            results.push(result);
+        }
+        let mut proof = MultiProof {
+            signatures: results,
+            challenge: None,
+            counted: None,
+        };
+
+        let nonce = proof
+            .verified()
+            .next()
+            .and_then(VerifiedProof::nonce)
+            .map(str::to_owned);
+        let nonce = match nonce {
+            Some(nonce) => nonce,
+            None => {
+                return Err(MultiProofError {
+                    error: ProofError::NoSignatureVerified,
+                    proof,
+                })
+            }
+        };
+        if proof.verified().any(|p| p.nonce() != Some(nonce.as_str())) {
+            return Err(MultiProofError {
+                error: ProofError::NonceMismatch,
+                proof,
+            });
+        }
+
+        proof.challenge = match self.nonces.challenge(&nonce, SystemTime::now()).await {
+            Ok(challenge) => challenge,
+            Err(e) => {
+                return Err(MultiProofError {
+                    error: ProofError::NonceStore(e),
+                    proof,
+                })
+            }
+        };
+        if let Some(challenge) = &proof.challenge {
+            let counted = counted_signatures(&proof.signatures, challenge);
+            let met = counted.iter().filter(|c| **c).count();
+            let threshold = challenge.threshold;
+            proof.counted = Some(counted);
+            if met < threshold {
+                return Err(MultiProofError {
+                    error: ProofError::ThresholdNotMet { met, threshold },
+                    proof,
+                });
+            }
+        }
+        if let Err(error) = self.consume_in_store(&nonce).await {
+            return Err(MultiProofError { error, proof });
+        }
+        Ok(proof)
+    }
+
+    /// Replaces an enrolled key. `proof` is a general JWS JSON object over a
+    /// nonce, signed by the enrolled key (named by `kid`) and by its
+    /// replacement; the payload's `rotation` claim `{"kid", "new_jkt"}` is how
+    /// the enrolled key endorses the replacement. A [`Policy::bound_jkt`] must
+    /// be the enrolled key's. The nonce is consumed.
+    pub async fn rotate_key(
+        &self,
+        proof: &str,
+        policy: &Policy,
+    ) -> Result<RegisteredKey, RotationError> {
+        // The two signatures are by different keys, so the binding is checked
+        // against the enrolled key below rather than per signature.
+        let signature_policy = Policy {
+            bound_jkt: None,
+            ..policy.clone()
+        };
+        let signatures = jws_json::parse(proof).map_err(ProofError::Malformed)?;
+        if signatures.len() != 2 {
+            return Err(RotationError::Invalid(
+                "a rotation proof needs exactly two signatures".to_string(),
+            ));
+        }
+        let mut proofs = Vec::with_capacity(2);
+        for signature in &signatures {
+            proofs.push(
+                self.verify_signature_with_header(
+                    &signature.token,
+                    &signature.header,
+                    &signature_policy,
+                )
+                .await?,
+            );
+        }
+        let (old, new) = match (&proofs[0].enrolled, &proofs[1].enrolled) {
+            (Some(current), None) => (current, &proofs[1]),
+            (None, Some(current)) => (current, &proofs[0]),
+            _ => return Err(RotationError::Invalid(
+                "a rotation proof needs one signature by the enrolled key and one by the new key"
+                    .to_string(),
+            )),
+        };
+        let previous_jkt = Jwk::from_map(old.jwk.clone())
+            .map_err(|e| e.to_string())
+            .and_then(|jwk| thumbprint::sha256_thumbprint(&jwk))
+            .map_err(ProofError::InvalidKey)?;
+        if new.thumbprint == previous_jkt {
+            return Err(RotationError::Invalid(
+                "the new key must differ from the enrolled key".to_string(),
+            ));
+        }
+        if policy
+            .bound_jkt
+            .as_ref()
+            .is_some_and(|jkt| *jkt != previous_jkt)
+        {
+            return Err(ProofError::BoundKeyMismatch.into());
+        }
+
+        let payload = &new.payload;
+        self.check_claims(payload, policy)?;
+        let rotation = payload.claim("rotation");
+        if rotation.and_then(|r| r.get("kid")).and_then(Value::as_str) != Some(old.kid.as_str()) {
+            return Err(RotationError::Invalid(
+                "rotation.kid must name the enrolled key".to_string(),
+            ));
+        }
+        if rotation
+            .and_then(|r| r.get("new_jkt"))
+            .and_then(Value::as_str)
+            != Some(new.thumbprint.as_str())
+        {
+            return Err(RotationError::Invalid(
+                "rotation.new_jkt must be the new key's thumbprint".to_string(),
+            ));
+        }
+        let nonce = new.nonce().ok_or(ProofError::NonceMissing)?;
+        self.consume_nonce(nonce).await?;
+
+        let new_jwk: &Map<String, Value> = new.jwk.as_ref();
+        self.registry
+            .rotate(
+                &old.kid,
+                &old.jwk,
+                new_jwk.clone(),
+                previous_jkt,
+                new.thumbprint.clone(),
+                proof.to_owned(),
+            )
+            .map_err(RotationError::Registry)
+    }
+
+    /// Verifies an SD-JWT presentation: the issuer-signed JWT against
+    /// `issuers`, its disclosures, and a Key Binding JWT addressed to
+    /// `audience` and signed with the credential's `cnf.jwk`. The KB-JWT's
+    /// nonce is consumed.
+    pub async fn verify_presentation(
+        &self,
+        presentation: &str,
+        issuers: &IssuerKeys,
+        audience: &str,
+        policy: &Policy,
+    ) -> Result<VerifiedPresentation, PresentationError> {
+        let presentation = sd_jwt::parse(presentation).map_err(ProofError::Malformed)?;
+        let credential = issuers
+            .verify(presentation.issuer_jwt, &policy.allowed_algs)
+            .map_err(|e| {
+                PresentationError::Credential(format!("invalid issuer-signed JWT: {}", e))
+            })?;
+        let now = SystemTime::now();
+        claims::check_validity_period(&credential, policy.time.skew, now)
+            .map_err(|e| PresentationError::Credential(format!("credential {}", e)))?;
+        let disclosures =
+            sd_jwt::check_disclosures(credential.claims_set(), &presentation.disclosures)
+                .map_err(PresentationError::Credential)?;
+
+        let cnf_jwk = credential
+            .claim("cnf")
+            .and_then(|cnf| cnf.get("jwk"))
+            .and_then(Value::as_object)
+            .ok_or_else(|| {
+                PresentationError::Credential("cnf.jwk missing in credential".to_string())
+            })?;
+        let kb_header = decode_jwt_header(presentation.kb_jwt)
+            .map_err(|e| ProofError::Malformed(e.to_string()))?;
+        let proof = self.verify_with_jwk(presentation.kb_jwt, &kb_header, cnf_jwk, policy)?;
+
+        sd_jwt::check_kb_jwt(&proof.header, &proof.payload, &presentation, audience)
+            .map_err(ProofError::InvalidClaims)?;
+        let time_policy = TimePolicy {
+            require_iat: true,
+            ..policy.time.clone()
+        };
+        claims::check_time_claims(&proof.payload, &time_policy, now)
+            .and_then(|()| claims::check_binding_claims(&proof.payload, &policy.binding))
+            .map_err(ProofError::InvalidClaims)?;
+        let nonce = proof.nonce().ok_or(ProofError::NonceMissing)?;
+        self.consume_nonce(nonce).await?;
+
+        Ok(VerifiedPresentation {
+            credential,
+            disclosures,
+            proof,
+        })
+    }
+
+    /// Verifies a DPoP proof bound to `request`. Its key must be the embedded
+    /// `jwk`, its `jti` new to `jtis`, and its `nonce` one this verifier
+    /// issued, which is consumed.
+    pub async fn verify_dpop(
+        &self,
+        token: &str,
+        request: &DpopRequest<'_>,
+        jtis: &JtiCache,
+        policy: &Policy,
+    ) -> Result<VerifiedProof, DpopError> {
+        let header =
+            decode_jwt_header(token).map_err(|e| DpopError::InvalidProof(e.to_string()))?;
+        let jwk = dpop::proof_jwk(&header)?;
+        let proof = self
+            .verify_with_jwk(token, &header, jwk, policy)
+            .map_err(|e| DpopError::InvalidProof(e.to_string()))?;
+        dpop::check_proof(&proof.header, &proof.payload, request)?;
+
+        let now = SystemTime::now();
+        let time_policy = TimePolicy {
+            require_iat: true,
+            ..policy.time.clone()
+        };
+        claims::check_time_claims(&proof.payload, &time_policy, now)
+            .map_err(DpopError::InvalidProof)?;
+        let jti = proof.payload.jwt_id().unwrap_or_default();
+        let forget_at = now + time_policy.max_age + time_policy.skew * 2;
+        if !jtis.insert(jti, forget_at, now) {
+            return Err(DpopError::InvalidProof(
+                "jti has already been used".to_string(),
+            ));
+        }
+
+        let nonce = proof
+            .nonce()
+            .ok_or_else(|| DpopError::UseNonce("nonce missing in DPoP proof".to_string()))?;
+        match self.consume_nonce(nonce).await {
+            Ok(()) => Ok(proof),
+            Err(ProofError::NonceStore(e)) => Err(DpopError::NonceStore(e)),
+            Err(e) => Err(DpopError::UseNonce(e.to_string())),
+        }
+    }
+
+    /// Verifies a wallet's `openid4vci-proof+jwt` key proof for the Credential
+    /// Issuer in `oid4vci`. Its `nonce` must be a `c_nonce` this verifier
+    /// issued, which is consumed.
+    pub async fn verify_openid4vci(
+        &self,
+        token: &str,
+        oid4vci: &Oid4vciPolicy,
+        policy: &Policy,
+    ) -> Result<VerifiedProof, Oid4vciError> {
+        let proof = self
+            .verify_signature(token, policy)
+            .await
+            .map_err(|e| Oid4vciError::InvalidProof(e.to_string()))?;
+        openid4vci::check_proof(&proof.header, &proof.payload, oid4vci)?;
+        let time_policy = TimePolicy {
+            require_iat: true,
+            ..policy.time.clone()
+        };
+        claims::check_time_claims(&proof.payload, &time_policy, SystemTime::now())
+            .map_err(Oid4vciError::InvalidProof)?;
+
+        let nonce = proof
+            .nonce()
+            .ok_or_else(|| Oid4vciError::InvalidNonce("c_nonce missing".to_string()))?;
+        let message = match self.consume_nonce(nonce).await {
+            Ok(()) => return Ok(proof),
+            Err(ProofError::NonceStore(e)) => return Err(Oid4vciError::NonceStore(e)),
+            Err(ProofError::NonceExpired) => "c_nonce expired".to_string(),
+            Err(ProofError::NonceReused) => "c_nonce has already been used".to_string(),
+            Err(ProofError::NonceUnknown) => "invalid c_nonce".to_string(),
+            Err(e) => e.to_string(),
+        };
+        Err(Oid4vciError::InvalidNonce(message))
+    }
+
+    /// Checks the time and binding claims of a proof.
+    pub fn check_claims(&self, payload: &JwtPayload, policy: &Policy) -> Result<(), ProofError> {
+        claims::check_time_claims(payload, &policy.time, SystemTime::now())
+            .and_then(|()| claims::check_binding_claims(payload, &policy.binding))
+            .map_err(ProofError::InvalidClaims)
+    }
+
+    /// Consumes the nonce of a single proof. Nonces of threshold challenges
+    /// are refused.
+    pub async fn consume_nonce(&self, nonce: &str) -> Result<(), ProofError> {
+        let challenge = self
+            .nonces
+            .challenge(nonce, SystemTime::now())
+            .await
+            .map_err(ProofError::NonceStore)?;
+        if challenge.is_some() {
+            return Err(ProofError::ThresholdRequired);
+        }
+        self.consume_in_store(nonce).await
+    }
+
+    async fn consume_in_store(&self, nonce: &str) -> Result<(), ProofError> {
+        match self.nonces.consume(nonce, SystemTime::now()).await {
+            Ok(ConsumeOutcome::Consumed) => Ok(()),
+            Ok(ConsumeOutcome::Expired) => Err(ProofError::NonceExpired),
+            Ok(ConsumeOutcome::Unknown) => Err(ProofError::NonceUnknown),
+            Ok(ConsumeOutcome::Reused) => Err(ProofError::NonceReused),
+            Err(e) => Err(ProofError::NonceStore(e)),
+        }
+    }
+
+    /// Resolves the key of `token` from its header (an `x5c` or `x5u` chain,
+    /// an embedded `jwk`, a `jku` JWK Set, the DID or the enrolled key its
+    /// `kid` names) and verifies its signature. Claims are left to the caller.
+    pub async fn verify_signature(
+        &self,
+        token: &str,
+        policy: &Policy,
+    ) -> Result<VerifiedProof, ProofError> {
+        let header_value =
+            decode_jwt_header(token).map_err(|e| ProofError::Malformed(e.to_string()))?;
+        self.verify_signature_with_header(token, &header_value, policy)
+            .await
+    }
+
+    /// Like `verify_signature`, but resolves the key from `header_value`,
+    /// which may carry members outside the protected header of `token`.
+    pub async fn verify_signature_with_header(
+        &self,
+        token: &str,
+        header_value: &Value,
+        policy: &Policy,
+    ) -> Result<VerifiedProof, ProofError> {
+        if let Some(x5c) = header_value.get("x5c") {
+            return self.verify_with_certificate(token, header_value, x5c, policy);
+        }
+        if let Some(x5u) = header_value.get("x5u") {
+            let x5u = x5u
+                .as_str()
+                .ok_or_else(|| ProofError::InvalidKey("x5u must be a string".to_string()))?;
+            let x5c = self
+                .remote_keys()?
+                .x5c(x5u)
+                .await
+                .map_err(ProofError::RemoteKey)?;
+            return self.verify_with_certificate(token, header_value, &x5c, policy);
+        }
+
+        let kid = header_value.get("kid").and_then(Value::as_str);
+        let mut resolved_did = None;
+        let (jwk_map, enrolled) = match (header_value.get("jwk"), header_value.get("jku")) {
+            (Some(Value::Object(map)), _) => (map.clone(), None),
+            (Some(_), _) => {
+                return Err(ProofError::InvalidKey(
+                    "JWK is not a JSON object".to_string(),
+                ))
+            }
+            (None, Some(jku)) => {
+                let jku = jku
+                    .as_str()
+                    .ok_or_else(|| ProofError::InvalidKey("jku must be a string".to_string()))?;
+                let kid =
+                    kid.ok_or_else(|| ProofError::InvalidKey("jku requires a kid".to_string()))?;
+                let jwk = self
+                    .remote_keys()?
+                    .jwk(jku, kid)
+                    .await
+                    .map_err(ProofError::RemoteKey)?;
+                (jwk, None)
+            }
+            (None, None) => match kid {
+                Some(kid) if did::is_did(kid) => {
+                    let resolved = did::resolve(kid).map_err(ProofError::InvalidKey)?;
+                    resolved_did = Some(resolved.did);
+                    (resolved.jwk, None)
+                }
+                Some(kid) => match self.registry.get(kid) {
+                    Some(key) => (key.jwk.clone(), Some(key)),
+                    None => return Err(ProofError::InvalidKey(format!("unknown kid {:?}", kid))),
+                },
+                None => {
+                    return Err(ProofError::InvalidKey(
+                        "JWK or kid missing in header".to_string(),
+                    ))
+                }
+            },
+        };
+        let mut proof = self.verify_with_jwk(token, header_value, &jwk_map, policy)?;
+        proof.enrolled = enrolled;
+        proof.did = resolved_did;
+        Ok(proof)
+    }
+
+    fn remote_keys(&self) -> Result<&RemoteKeys, ProofError> {
+        self.remote_keys
+            .as_ref()
+            .ok_or_else(|| ProofError::InvalidKey("jku and x5u proofs are not enabled".to_string()))
+    }
+
+    /// Verifies a proof signed with the leaf key of its `x5c` chain. An
+    /// embedded `jwk`, if any, must be that same key.
+    fn verify_with_certificate(
+        &self,
+        token: &str,
+        header_value: &Value,
+        x5c: &Value,
+        policy: &Policy,
+    ) -> Result<VerifiedProof, ProofError> {
+        let anchors = self
+            .trust_anchors
+            .as_ref()
+            .ok_or_else(|| ProofError::InvalidKey("x5c proofs are not enabled".to_string()))?;
+        let certified = x5c::validate_chain(x5c, anchors, SystemTime::now(), policy.time.skew)
+            .map_err(ProofError::Certificate)?;
+
+        let mut proof = self.verify_with_jwk(token, header_value, &certified.jwk, policy)?;
+        if let Some(embedded) = header_value.get("jwk") {
+            let same_key = embedded
+                .as_object()
+                .and_then(|map| Jwk::from_map(map.clone()).ok())
+                .and_then(|jwk| thumbprint::sha256_thumbprint(&jwk).ok())
+                .is_some_and(|t| t == proof.thumbprint);
+            if !same_key {
+                return Err(ProofError::Certificate(
+                    "jwk does not match the x5c leaf certificate key".to_string(),
+                ));
+            }
+        }
+        proof.certificate = Some(certified);
+        Ok(proof)
+    }
+
+    /// Verifies the signature of `token` with the public key `jwk_map`,
+    /// wherever the key came from.
+    pub fn verify_with_jwk(
+        &self,
+        token: &str,
+        header_value: &Value,
+        jwk_map: &Map<String, Value>,
+        policy: &Policy,
+    ) -> Result<VerifiedProof, ProofError> {
+        let alg = match header_value.get("alg").and_then(|v| v.as_str()) {
+            Some(name) => name
+                .parse::<ProofAlg>()
+                .map_err(ProofError::UnsupportedAlg)?,
+            None => {
+                return Err(ProofError::UnsupportedAlg(
+                    "alg missing in header".to_string(),
+                ))
+            }
+        };
+        if !policy.allowed_algs.contains(&alg) {
+            return Err(ProofError::UnsupportedAlg(format!(
+                "alg {} is not accepted",
+                alg
+            )));
+        }
+
+        jwk_policy::check_public_jwk(jwk_map, alg).map_err(ProofError::KeyPolicy)?;
+
+        let jwk = Jwk::from_map(jwk_map.clone())
+            .map_err(|e| ProofError::InvalidKey(format!("failed to parse JWK: {}", e)))?;
+        alg.check_key(&jwk).map_err(ProofError::InvalidKey)?;
+
+        let verifier = alg
+            .verifier_from_jwk(&jwk)
+            .map_err(|e| ProofError::InvalidKey(e.to_string()))?;
+
+        let (payload, header) =
+            jwt::decode_with_verifier(token, &*verifier).map_err(|e| match e {
+                JoseError::InvalidSignature(_) => ProofError::BadSignature(e.to_string()),
+                _ => ProofError::Malformed(e.to_string()),
+            })?;
+
+        let thumbprint = thumbprint::sha256_thumbprint(&jwk).map_err(ProofError::InvalidKey)?;
+        if let Some(bound_jkt) = &policy.bound_jkt {
+            if *bound_jkt != thumbprint {
+                return Err(ProofError::BoundKeyMismatch);
+            }
+        }
+
+        Ok(VerifiedProof {
+            jwk,
+            header,
+            payload,
+            thumbprint,
+            enrolled: None,
+            certificate: None,
+            did: None,
+        })
+    }
+}
+
+/// For each signature, whether it counts towards `challenge`: it verified
+/// with one of the challenge's registered keys, and no earlier signature was
+/// made with the same key.
+fn counted_signatures(
+    results: &[Result<VerifiedProof, ProofError>],
+    challenge: &ThresholdChallenge,
+) -> Vec<bool> {
+    let mut seen = HashSet::new();
+    results
+        .iter()
+        .map(|result| match result {
+            Ok(proof) => proof.enrolled.as_ref().is_some_and(|key| {
+                challenge.includes(&key.kid) && seen.insert(proof.thumbprint.clone())
+            }),
+            Err(_) => false,
+        })
+        .collect()
+}
+
+/// Decodes the protected header of a compact JWS without verifying it.
+pub fn decode_jwt_header(token: &str) -> Result<Value, JoseError> {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 {
+        return Err(JoseError::InvalidJwtFormat(anyhow!(
+            "JWT must have 3 parts"
+        )));
+    }
+    let header_bytes = general_purpose::URL_SAFE_NO_PAD
+        .decode(parts[0])
+        .map_err(|e| JoseError::InvalidJwtFormat(anyhow!("Base64 decode error: {}", e)))?;
+    let header_value: Value = serde_json::from_slice(&header_bytes)
+        .map_err(|e| JoseError::InvalidJwtFormat(anyhow!("JSON decode error: {}", e)))?;
+    Ok(header_value)
+}
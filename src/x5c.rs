@@ -0,0 +1,342 @@
+//! X.509 certificate chains (`x5c`) validated against configured trust anchors.
+
+use anyhow::{anyhow, Context};
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Map, Value};
+use std::fmt::Display;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use x509_parser::{
+    certificate::X509Certificate,
+    extensions::GeneralName,
+    oid_registry::{
+        Oid, OID_X509_EXT_BASIC_CONSTRAINTS, OID_X509_EXT_KEY_USAGE, OID_X509_EXT_SUBJECT_ALT_NAME,
+    },
+    parse_x509_certificate,
+    pem::Pem,
+    public_key::PublicKey,
+};
+
+/// Longest chain accepted, leaf included.
+const MAX_CHAIN_LEN: usize = 10;
+/// Extensions whose meaning validation enforces. Any other extension marked
+/// critical makes a certificate unusable.
+const HANDLED_EXTENSIONS: [(Oid<'static>, &str); 3] = [
+    (OID_X509_EXT_BASIC_CONSTRAINTS, "basic constraints"),
+    (OID_X509_EXT_KEY_USAGE, "key usage"),
+    (OID_X509_EXT_SUBJECT_ALT_NAME, "subject alternative name"),
+];
+
+/// DER-encoded CA certificates that chains must lead to.
+pub struct TrustAnchors {
+    certs: Vec<Vec<u8>>,
+}
+
+impl TrustAnchors {
+    /// Loads every certificate in a PEM bundle.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let mut certs = Vec::new();
+        for pem in Pem::iter_from_buffer(&contents) {
+            let pem = pem.with_context(|| format!("parsing {}", path.display()))?;
+            if pem.label != "CERTIFICATE" {
+                continue;
+            }
+            parse_x509_certificate(&pem.contents)
+                .map_err(|e| anyhow!("invalid trust anchor in {}: {}", path.display(), e))?;
+            certs.push(pem.contents);
+        }
+        if certs.is_empty() {
+            return Err(anyhow!("{} contains no certificates", path.display()));
+        }
+        Ok(TrustAnchors { certs })
+    }
+}
+
+/// What a validated chain says about its leaf.
+#[derive(Debug, Clone)]
+pub struct CertifiedKey {
+    /// The leaf's public key as a JWK, which the proof must be signed with.
+    pub jwk: Map<String, Value>,
+    pub subject: String,
+    pub sans: Vec<String>,
+}
+
+/// Validates an `x5c` header value against `anchors` and returns the leaf's
+/// key and names.
+pub fn validate_chain(
+    x5c: &Value,
+    anchors: &TrustAnchors,
+    now: SystemTime,
+    skew: Duration,
+) -> Result<CertifiedKey, String> {
+    let encoded = x5c
+        .as_array()
+        .filter(|certs| !certs.is_empty())
+        .ok_or_else(|| "x5c must be a non-empty array".to_string())?;
+    if encoded.len() > MAX_CHAIN_LEN {
+        return Err(format!(
+            "x5c chains are limited to {} certificates",
+            MAX_CHAIN_LEN
+        ));
+    }
+    let ders = encoded
+        .iter()
+        .map(|cert| {
+            cert.as_str()
+                .and_then(|b64| general_purpose::STANDARD.decode(b64).ok())
+                .ok_or_else(|| "x5c entries must be base64 DER certificates".to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let chain = ders
+        .iter()
+        .map(|der| parse(der))
+        .collect::<Result<Vec<_>, _>>()?;
+    let anchor_certs = anchors
+        .certs
+        .iter()
+        .map(|der| parse(der))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let skew = skew.as_secs() as i64;
+
+    let leaf = &chain[0];
+    check_extensions(leaf)?;
+    check_validity(leaf, now, skew)?;
+    match leaf.basic_constraints() {
+        Ok(Some(constraints)) if constraints.value.ca => {
+            return Err("leaf certificate must not be a CA certificate".to_string());
+        }
+        Ok(_) => {}
+        Err(e) => return Err(invalid_extension(leaf, "basic constraints", e)),
+    }
+    match leaf.key_usage() {
+        Ok(Some(key_usage)) if !key_usage.value.digital_signature() => {
+            return Err("leaf certificate key usage does not allow digitalSignature".to_string());
+        }
+        Ok(_) => {}
+        Err(e) => return Err(invalid_extension(leaf, "key usage", e)),
+    }
+
+    for (depth, pair) in chain.windows(2).enumerate() {
+        let (child, issuer) = (&pair[0], &pair[1]);
+        check_extensions(issuer)?;
+        check_validity(issuer, now, skew)?;
+        check_issued_by(child, issuer, depth)?;
+    }
+
+    let top = &chain[chain.len() - 1];
+    let top_der = &ders[ders.len() - 1];
+    let anchored = anchors.certs.iter().any(|anchor| anchor == top_der)
+        || anchor_certs.iter().any(|anchor| {
+            check_extensions(anchor).is_ok()
+                && check_validity(anchor, now, skew).is_ok()
+                && check_issued_by(top, anchor, chain.len() - 1).is_ok()
+        });
+    if !anchored {
+        return Err("certificate chain does not lead to a trust anchor".to_string());
+    }
+
+    Ok(CertifiedKey {
+        jwk: public_jwk(leaf)?,
+        subject: leaf.subject().to_string(),
+        sans: subject_alt_names(leaf),
+    })
+}
+
+/// The public key of a DER certificate as a JWK, without validating the
+/// certificate.
+pub fn certificate_jwk(der: &[u8]) -> Result<Map<String, Value>, String> {
+    public_jwk(&parse(der)?)
+}
+
+fn parse(der: &[u8]) -> Result<X509Certificate<'_>, String> {
+    match parse_x509_certificate(der) {
+        Ok(([], cert)) => Ok(cert),
+        Ok(_) => Err("trailing data after certificate".to_string()),
+        Err(e) => Err(format!("invalid certificate: {}", e)),
+    }
+}
+
+/// Rejects unsupported critical extensions, and handled extensions that are
+/// malformed or appear more than once.
+fn check_extensions(cert: &X509Certificate<'_>) -> Result<(), String> {
+    for ext in cert.extensions() {
+        let name = match HANDLED_EXTENSIONS.iter().find(|(oid, _)| *oid == ext.oid) {
+            Some((_, name)) => *name,
+            None if ext.critical => {
+                return Err(format!(
+                    "certificate {} has an unsupported critical extension {}",
+                    cert.subject(),
+                    ext.oid.to_id_string()
+                ));
+            }
+            None => continue,
+        };
+        if let Some(e) = ext.parsed_extension().error() {
+            return Err(invalid_extension(cert, name, e));
+        }
+        if let Err(e) = cert.get_extension_unique(&ext.oid) {
+            return Err(invalid_extension(cert, name, e));
+        }
+    }
+    Ok(())
+}
+
+fn invalid_extension(cert: &X509Certificate<'_>, name: &str, e: impl Display) -> String {
+    format!(
+        "certificate {} has an invalid {} extension: {}",
+        cert.subject(),
+        name,
+        e
+    )
+}
+
+fn check_validity(cert: &X509Certificate<'_>, now: i64, skew: i64) -> Result<(), String> {
+    let validity = cert.validity();
+    if now + skew < validity.not_before.timestamp() {
+        return Err(format!("certificate {} is not yet valid", cert.subject()));
+    }
+    if now - skew > validity.not_after.timestamp() {
+        return Err(format!("certificate {} has expired", cert.subject()));
+    }
+    Ok(())
+}
+
+/// Checks that `issuer` is a CA allowed to sign `child`, which sits
+/// `depth` CA certificates below it, and that it did.
+fn check_issued_by(
+    child: &X509Certificate<'_>,
+    issuer: &X509Certificate<'_>,
+    depth: usize,
+) -> Result<(), String> {
+    if child.issuer().as_raw() != issuer.subject().as_raw() {
+        return Err(format!(
+            "certificate {} was not issued by {}",
+            child.subject(),
+            issuer.subject()
+        ));
+    }
+    match issuer.basic_constraints() {
+        Ok(Some(constraints)) if constraints.value.ca => {
+            if let Some(max) = constraints.value.path_len_constraint {
+                if depth > max as usize {
+                    return Err(format!(
+                        "path length constraint of {} exceeded",
+                        issuer.subject()
+                    ));
+                }
+            }
+        }
+        Ok(_) => return Err(format!("{} is not a CA certificate", issuer.subject())),
+        Err(e) => return Err(invalid_extension(issuer, "basic constraints", e)),
+    }
+    match issuer.key_usage() {
+        Ok(Some(key_usage)) if !key_usage.value.key_cert_sign() => {
+            return Err(format!(
+                "key usage of {} does not allow keyCertSign",
+                issuer.subject()
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => return Err(invalid_extension(issuer, "key usage", e)),
+    }
+    child
+        .verify_signature(Some(issuer.public_key()))
+        .map_err(|_| format!("bad signature on certificate {}", child.subject()))
+}
+
+/// Converts the leaf's SubjectPublicKeyInfo to a public JWK.
+fn public_jwk(cert: &X509Certificate<'_>) -> Result<Map<String, Value>, String> {
+    let spki = cert.public_key();
+    let b64 = |bytes: &[u8]| general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+    let jwk = match spki.algorithm.algorithm.to_id_string().as_str() {
+        "1.2.840.10045.2.1" => {
+            let curve = spki
+                .algorithm
+                .parameters
+                .as_ref()
+                .and_then(|p| p.as_oid().ok())
+                .map(|oid| oid.to_id_string());
+            let (crv, size) = match curve.as_deref() {
+                Some("1.2.840.10045.3.1.7") => ("P-256", 32),
+                Some("1.3.132.0.34") => ("P-384", 48),
+                Some("1.3.132.0.35") => ("P-521", 66),
+                _ => return Err("unsupported EC curve in certificate".to_string()),
+            };
+            let point: &[u8] = &spki.subject_public_key.data;
+            if point.len() != 1 + 2 * size || point[0] != 0x04 {
+                return Err("certificate EC key must be an uncompressed point".to_string());
+            }
+            json!({
+                "kty": "EC",
+                "crv": crv,
+                "x": b64(&point[1..1 + size]),
+                "y": b64(&point[1 + size..]),
+            })
+        }
+        "1.3.101.112" | "1.3.101.113" => {
+            let crv = if spki.algorithm.algorithm.to_id_string() == "1.3.101.112" {
+                "Ed25519"
+            } else {
+                "Ed448"
+            };
+            json!({
+                "kty": "OKP",
+                "crv": crv,
+                "x": b64(&spki.subject_public_key.data),
+            })
+        }
+        "1.2.840.113549.1.1.1" => match spki.parsed() {
+            Ok(PublicKey::RSA(rsa)) => json!({
+                "kty": "RSA",
+                "n": b64(strip_leading_zeros(rsa.modulus)),
+                "e": b64(strip_leading_zeros(rsa.exponent)),
+            }),
+            _ => return Err("invalid RSA key in certificate".to_string()),
+        },
+        oid => return Err(format!("unsupported certificate key algorithm {}", oid)),
+    };
+    match jwk {
+        Value::Object(map) => Ok(map),
+        _ => unreachable!(),
+    }
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn subject_alt_names(cert: &X509Certificate<'_>) -> Vec<String> {
+    let sans = match cert.subject_alternative_name() {
+        Ok(Some(sans)) => sans,
+        _ => return Vec::new(),
+    };
+    sans.value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(dns) => Some(format!("DNS:{}", dns)),
+            GeneralName::RFC822Name(email) => Some(format!("email:{}", email)),
+            GeneralName::URI(uri) => Some(format!("URI:{}", uri)),
+            GeneralName::IPAddress(ip) => match ip.len() {
+                4 => Some(format!("IP:{}", Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]))),
+                16 => {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(ip);
+                    Some(format!("IP:{}", Ipv6Addr::from(octets)))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,103 @@
+//! A policy's `bound_jkt`, the TLS client certificate's key, must sign the
+//! proof.
+
+use josekit::jwk::Jwk;
+use key_ownership_prover::algs::KeyType;
+use key_ownership_prover::holder::{self, KeyReference, ProofClaims};
+use key_ownership_prover::store::InMemoryNonceStore;
+use key_ownership_prover::{jws_json, thumbprint, Policy, ProofError, Verifier};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn verifier() -> Verifier {
+    Verifier::new(Arc::new(InMemoryNonceStore::new()))
+}
+
+async fn nonce(verifier: &Verifier) -> String {
+    verifier
+        .issue_nonce(SystemTime::now() + Duration::from_secs(300), None)
+        .await
+        .unwrap()
+}
+
+fn proof(key: &Jwk, nonce: &str) -> String {
+    holder::sign_proof(
+        KeyType::P256.default_alg(),
+        key,
+        KeyReference::Jwk,
+        nonce,
+        &ProofClaims::default(),
+    )
+    .unwrap()
+}
+
+/// A policy bound to `key`, as for a request made with its certificate.
+fn bound_to(key: &Jwk) -> Policy {
+    Policy {
+        bound_jkt: Some(thumbprint::sha256_thumbprint(&key.to_public_key().unwrap()).unwrap()),
+        ..Policy::default()
+    }
+}
+
+#[tokio::test]
+async fn a_proof_must_be_signed_with_the_bound_key() {
+    let verifier = verifier();
+    let (key, other) = (
+        KeyType::P256.generate().unwrap(),
+        KeyType::P256.generate().unwrap(),
+    );
+    let nonce = nonce(&verifier).await;
+
+    let err = verifier
+        .verify(&proof(&other, &nonce), &bound_to(&key))
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, ProofError::BoundKeyMismatch), "{}", err);
+
+    // The mismatch left the nonce for the bound key's proof.
+    assert!(verifier
+        .verify(&proof(&key, &nonce), &bound_to(&key))
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn only_signatures_by_the_bound_key_verify() {
+    let verifier = verifier();
+    let (key, other) = (
+        KeyType::P256.generate().unwrap(),
+        KeyType::P256.generate().unwrap(),
+    );
+    let nonce = nonce(&verifier).await;
+    let bundle = |keys: &[&Jwk]| {
+        let tokens: Vec<String> = keys.iter().map(|key| proof(key, &nonce)).collect();
+        jws_json::parse_bundle(&json!(tokens).to_string()).unwrap()
+    };
+
+    let err = verifier
+        .verify_all(&bundle(&[&other]), &bound_to(&key))
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err.error, ProofError::NoSignatureVerified),
+        "{}",
+        err.error
+    );
+    assert!(matches!(
+        err.proof.signatures[0],
+        Err(ProofError::BoundKeyMismatch)
+    ));
+
+    let proof = verifier
+        .verify_all(&bundle(&[&key, &other]), &bound_to(&key))
+        .await
+        .unwrap_or_else(|e| panic!("bundle rejected: {}", e.error));
+    assert!(proof.signatures[0].is_ok());
+    assert!(matches!(
+        proof.signatures[1],
+        Err(ProofError::BoundKeyMismatch)
+    ));
+}
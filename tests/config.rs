@@ -0,0 +1,116 @@
+//! `kop-verifier --check-config`: the environment overrides the file, which
+//! overrides the defaults, and every problem is reported at once.
+
+use std::process::{Command, Output};
+use uuid::Uuid;
+
+/// Runs `--check-config` with only `env` set, and `toml` as the file if any.
+fn check_config(toml: Option<&str>, env: &[(&str, &str)]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_kop-verifier"));
+    command
+        .arg("--check-config")
+        .env_clear()
+        .envs(env.iter().copied());
+    let path = toml.map(|toml| {
+        let path = std::env::temp_dir().join(format!("kop-test-{}.toml", Uuid::new_v4()));
+        std::fs::write(&path, toml).unwrap();
+        path
+    });
+    if let Some(path) = &path {
+        command.arg("--config").arg(path);
+    }
+    let output = command.output().unwrap();
+    if let Some(path) = path {
+        std::fs::remove_file(path).unwrap();
+    }
+    output
+}
+
+fn accepted(output: &Output) -> String {
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(
+        output.status.success(),
+        "rejected: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+fn rejected(output: &Output) -> String {
+    assert!(!output.status.success(), "accepted");
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+const FILE: &str = r#"
+[server]
+bind = ["127.0.0.1:9000"]
+log_level = "warn"
+
+[nonces]
+ttl_secs = 120
+"#;
+
+#[test]
+fn defaults_apply_without_file_or_environment() {
+    let stdout = accepted(&check_config(None, &[]));
+    assert!(stdout.contains("bind: 127.0.0.1:8080"), "{}", stdout);
+    assert!(stdout.contains("nonce TTL: 300s"), "{}", stdout);
+    assert!(stdout.contains("log level: info"), "{}", stdout);
+}
+
+#[test]
+fn the_file_overrides_the_defaults() {
+    let stdout = accepted(&check_config(Some(FILE), &[]));
+    assert!(stdout.contains("bind: 127.0.0.1:9000"), "{}", stdout);
+    assert!(stdout.contains("nonce TTL: 120s"), "{}", stdout);
+    assert!(stdout.contains("log level: warn"), "{}", stdout);
+}
+
+#[test]
+fn the_environment_overrides_the_file() {
+    let stdout = accepted(&check_config(
+        Some(FILE),
+        &[
+            ("BIND_ADDRS", "127.0.0.1:9001,127.0.0.1:9002"),
+            ("NONCE_TTL_SECS", "60"),
+            ("LOG_LEVEL", "debug"),
+        ],
+    ));
+    assert!(
+        stdout.contains("bind: 127.0.0.1:9001, 127.0.0.1:9002"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("nonce TTL: 60s"), "{}", stdout);
+    assert!(stdout.contains("log level: debug"), "{}", stdout);
+}
+
+#[test]
+fn log_filters_are_those_env_logger_accepts() {
+    for filter in [
+        "actix_web",
+        "info,actix_web",
+        "warn,key_ownership_prover=debug",
+    ] {
+        accepted(&check_config(None, &[("LOG_LEVEL", filter)]));
+    }
+    for filter in ["info=loud", "a/b/c"] {
+        let stderr = rejected(&check_config(None, &[("LOG_LEVEL", filter)]));
+        assert!(
+            stderr.contains("server.log_level"),
+            "{}: {}",
+            filter,
+            stderr
+        );
+    }
+}
+
+#[test]
+fn every_problem_is_reported_together() {
+    let stderr = rejected(&check_config(
+        Some(FILE),
+        &[("NONCE_TTL_SECS", "0"), ("NONCE_STORE", "redis")],
+    ));
+    assert!(stderr.contains("nonces.ttl_secs"), "{}", stderr);
+    assert!(stderr.contains("nonces.store"), "{}", stderr);
+}
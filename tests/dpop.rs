@@ -0,0 +1,107 @@
+//! DPoP proofs are bound to the method, URL and access token of their request.
+
+use key_ownership_prover::algs::KeyType;
+use key_ownership_prover::dpop::{self, DpopError, DpopRequest, JtiCache};
+use key_ownership_prover::store::InMemoryNonceStore;
+use key_ownership_prover::{Policy, Verifier};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const HTU: &str = "https://verifier.example.com/verify";
+
+fn verifier() -> Verifier {
+    Verifier::new(Arc::new(InMemoryNonceStore::new()))
+}
+
+async fn nonce(verifier: &Verifier) -> String {
+    verifier
+        .issue_nonce(SystemTime::now() + Duration::from_secs(300), None)
+        .await
+        .unwrap()
+}
+
+fn request(access_token: Option<&str>) -> DpopRequest<'_> {
+    DpopRequest {
+        method: "POST",
+        url: HTU,
+        access_token,
+    }
+}
+
+/// Mints a P-256 proof with a fresh key.
+fn proof(htm: &str, htu: &str, nonce: Option<&str>, access_token: Option<&str>) -> String {
+    let private_key = KeyType::P256.generate().unwrap();
+    dpop::mint_proof(
+        KeyType::P256.default_alg(),
+        &private_key,
+        htm,
+        htu,
+        nonce,
+        access_token,
+    )
+    .unwrap()
+}
+
+#[tokio::test]
+async fn a_proof_for_the_request_verifies_once() {
+    let verifier = verifier();
+    let jtis = JtiCache::new();
+    let nonce = nonce(&verifier).await;
+    let token = proof("POST", &format!("{}?x=1", HTU), Some(&nonce), Some("at"));
+
+    let proof = verifier
+        .verify_dpop(&token, &request(Some("at")), &jtis, &Policy::default())
+        .await
+        .unwrap();
+    assert_eq!(proof.nonce(), Some(nonce.as_str()));
+
+    let err = verifier
+        .verify_dpop(&token, &request(Some("at")), &jtis, &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, DpopError::InvalidProof(_)), "{}", err);
+}
+
+#[tokio::test]
+async fn proofs_for_another_request_are_rejected() {
+    let verifier = verifier();
+    let cases = [
+        ("htm", proof("GET", HTU, None, None), None),
+        (
+            "htu",
+            proof("POST", "https://attacker.example.com/verify", None, None),
+            None,
+        ),
+        ("ath", proof("POST", HTU, None, Some("other")), Some("at")),
+        ("missing ath", proof("POST", HTU, None, None), Some("at")),
+        ("unexpected ath", proof("POST", HTU, None, Some("at")), None),
+    ];
+    for (case, token, access_token) in cases {
+        let err = verifier
+            .verify_dpop(
+                &token,
+                &request(access_token),
+                &JtiCache::new(),
+                &Policy::default(),
+            )
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("{} accepted", case));
+        assert_eq!(err.code(), "invalid_dpop_proof", "{}: {}", case, err);
+    }
+}
+
+#[tokio::test]
+async fn proofs_without_a_valid_nonce_ask_for_one() {
+    let verifier = verifier();
+    for nonce in [None, Some("not-issued")] {
+        let token = proof("POST", HTU, nonce, None);
+        let err = verifier
+            .verify_dpop(&token, &request(None), &JtiCache::new(), &Policy::default())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), "use_dpop_nonce", "{:?}: {}", nonce, err);
+    }
+}
@@ -0,0 +1,97 @@
+//! Embedded and enrolled JWKs must be well-formed public signature keys.
+
+use josekit::jwk::Jwk;
+use josekit::jws::JwsHeader;
+use josekit::jwt::{self, JwtPayload};
+use key_ownership_prover::algs::{KeyType, ProofAlg};
+use key_ownership_prover::jwk_policy::{self, JwkPolicyError};
+use key_ownership_prover::store::InMemoryNonceStore;
+use key_ownership_prover::{Policy, ProofError, Verifier};
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn public_jwk(key_type: KeyType) -> Map<String, Value> {
+    let private_key = key_type.generate().unwrap();
+    private_key.to_public_key().unwrap().as_ref().clone()
+}
+
+#[test]
+fn public_keys_of_every_type_pass() {
+    for key_type in KeyType::ALL {
+        let jwk = public_jwk(key_type);
+        assert_eq!(
+            jwk_policy::check_public_jwk(&jwk, key_type.default_alg()),
+            Ok(()),
+            "{}",
+            key_type
+        );
+    }
+}
+
+#[test]
+fn rejected_keys_report_their_code() {
+    // Each case sets a member of a valid P-256 key, or removes it for `None`.
+    let cases = [
+        ("d", Some(json!("AAAA")), "jwk_private_key"),
+        ("k", Some(json!("AAAA")), "jwk_private_key"),
+        ("use", Some(json!("enc")), "jwk_invalid_use"),
+        ("key_ops", Some(json!(["sign"])), "jwk_invalid_key_ops"),
+        ("alg", Some(json!("ES384")), "jwk_alg_mismatch"),
+        ("y", None, "jwk_invalid_params"),
+        ("x", Some(json!("")), "jwk_invalid_params"),
+        ("kty", Some(json!("oct")), "jwk_invalid_params"),
+        ("kty", None, "jwk_invalid_params"),
+    ];
+    for (member, value, code) in cases {
+        let mut jwk = public_jwk(KeyType::P256);
+        match &value {
+            Some(value) => jwk.insert(member.to_string(), value.clone()),
+            None => jwk.remove(member),
+        };
+        let err = jwk_policy::check_public_jwk(&jwk, ProofAlg::ES256)
+            .err()
+            .unwrap_or_else(|| panic!("{} = {:?} accepted", member, value));
+        assert_eq!(err.code(), code, "{} = {:?}: {}", member, value, err);
+    }
+
+    let mut okp = public_jwk(KeyType::Ed25519);
+    okp.insert("y".into(), json!("AAAA"));
+    assert!(jwk_policy::check_public_key(&okp).is_err());
+}
+
+#[tokio::test]
+async fn a_proof_embedding_a_private_key_is_rejected_before_its_nonce_is_used() {
+    let verifier = Verifier::new(Arc::new(InMemoryNonceStore::new()));
+    let nonce = verifier
+        .issue_nonce(SystemTime::now() + Duration::from_secs(300), None)
+        .await
+        .unwrap();
+    let private_key = KeyType::P256.generate().unwrap();
+    let sign = |header_jwk: &Jwk| {
+        let mut header = JwsHeader::new();
+        header.set_jwk(header_jwk.clone());
+        let mut payload = JwtPayload::new();
+        payload.set_claim("nonce", Some(json!(nonce))).unwrap();
+        payload.set_issued_at(&SystemTime::now());
+        let signer = ProofAlg::ES256.signer_from_jwk(&private_key).unwrap();
+        jwt::encode_with_signer(&payload, &header, &*signer).unwrap()
+    };
+
+    let err = verifier
+        .verify(&sign(&private_key), &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(&err, ProofError::KeyPolicy(JwkPolicyError::PrivateMember(name)) if name == "d"),
+        "{}",
+        err
+    );
+
+    let public_key = private_key.to_public_key().unwrap();
+    assert!(verifier
+        .verify(&sign(&public_key), &Policy::default())
+        .await
+        .is_ok());
+}
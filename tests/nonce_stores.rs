@@ -0,0 +1,166 @@
+//! Every nonce store tells expired, reused and unknown nonces apart, and keeps
+//! threshold challenges with their nonce.
+
+use key_ownership_prover::algs::KeyType;
+use key_ownership_prover::holder::{self, KeyReference, ProofClaims};
+use key_ownership_prover::store::{
+    ConsumeOutcome, FileNonceStore, InMemoryNonceStore, SqliteNonceStore, StatelessNonceStore,
+};
+use key_ownership_prover::threshold::ThresholdChallenge;
+use key_ownership_prover::{NonceStore, Policy, Verifier};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("kop-test-{}-{}", Uuid::new_v4(), name))
+}
+
+fn stores() -> Vec<(&'static str, Arc<dyn NonceStore>)> {
+    vec![
+        ("memory", Arc::new(InMemoryNonceStore::new())),
+        (
+            "sqlite",
+            Arc::new(SqliteNonceStore::open(temp_path("nonces.db")).unwrap()),
+        ),
+        (
+            "file",
+            Arc::new(FileNonceStore::open(temp_path("nonces.log")).unwrap()),
+        ),
+        (
+            "stateless",
+            Arc::new(StatelessNonceStore::new(vec![vec![7; 32]]).unwrap()),
+        ),
+    ]
+}
+
+/// Signs a proof for `nonce` and returns the error code it is rejected with.
+async fn rejection(verifier: &Verifier, nonce: &str) -> Option<&'static str> {
+    let private_key = KeyType::P256.generate().unwrap();
+    let token = holder::sign_proof(
+        KeyType::P256.default_alg(),
+        &private_key,
+        KeyReference::Jwk,
+        nonce,
+        &ProofClaims::default(),
+    )
+    .unwrap();
+    verifier
+        .verify(&token, &Policy::default())
+        .await
+        .err()
+        .map(|e| e.code())
+}
+
+#[tokio::test]
+async fn fresh_nonces_are_consumed_once() {
+    for (name, store) in stores() {
+        let verifier = Verifier::new(store);
+        let nonce = verifier
+            .issue_nonce(SystemTime::now() + Duration::from_secs(300), None)
+            .await
+            .unwrap();
+        assert_eq!(rejection(&verifier, &nonce).await, None, "{}", name);
+        assert_eq!(
+            rejection(&verifier, &nonce).await,
+            Some("nonce_reused"),
+            "{}",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn expired_nonces_are_rejected() {
+    for (name, store) in stores() {
+        let verifier = Verifier::new(store);
+        let nonce = verifier
+            .issue_nonce(SystemTime::now() - Duration::from_secs(1), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            rejection(&verifier, &nonce).await,
+            Some("nonce_expired"),
+            "{}",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn unknown_nonces_are_rejected() {
+    for (name, store) in stores() {
+        let verifier = Verifier::new(store);
+        for nonce in [
+            Uuid::new_v4().to_string(),
+            "v1.AAAA.1.99999999999.AAAA".to_string(),
+        ] {
+            assert_eq!(
+                rejection(&verifier, &nonce).await,
+                Some("nonce_unknown"),
+                "{} {}",
+                name,
+                nonce
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn challenges_are_kept_with_their_nonce() {
+    let challenge = ThresholdChallenge::new(vec!["d1".to_string(), "d2".to_string()], 2).unwrap();
+    for (name, store) in stores() {
+        let now = SystemTime::now();
+        let nonce = store
+            .issue(now + Duration::from_secs(300), Some(&challenge))
+            .await
+            .unwrap();
+        let plain = store
+            .issue(now + Duration::from_secs(300), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.challenge(&nonce, now).await.unwrap(),
+            Some(challenge.clone()),
+            "{}",
+            name
+        );
+        assert_eq!(
+            store.challenge(&plain, now).await.unwrap(),
+            None,
+            "{}",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn expired_nonces_stay_expired() {
+    for (name, store) in stores() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(120);
+        let never_consumed = store
+            .issue(now + Duration::from_secs(60), None)
+            .await
+            .unwrap();
+        let consumed = store
+            .issue(now + Duration::from_secs(60), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            store.consume(&consumed, now).await.unwrap(),
+            ConsumeOutcome::Consumed,
+            "{}",
+            name
+        );
+        for nonce in [&never_consumed, &consumed, &never_consumed, &consumed] {
+            assert_eq!(
+                store.consume(nonce, later).await.unwrap(),
+                ConsumeOutcome::Expired,
+                "{}",
+                name
+            );
+        }
+    }
+}
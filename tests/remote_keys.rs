@@ -0,0 +1,170 @@
+//! `jku` proofs against a local stand-in key server.
+
+use josekit::jwk::Jwk;
+use josekit::jws::JwsHeader;
+use josekit::jwt::{self, JwtPayload};
+use key_ownership_prover::algs::KeyType;
+use key_ownership_prover::remote_keys::{RemoteKeyPolicy, RemoteKeys};
+use key_ownership_prover::store::InMemoryNonceStore;
+use key_ownership_prover::{Policy, Verifier};
+use serde_json::json;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Serves `body` as JSON to every request and counts the requests.
+async fn serve_jwks(body: String) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::SeqCst);
+            let body = body.clone();
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    (addr, hits)
+}
+
+fn verifier(allowed_host: &str, policy: RemoteKeyPolicy) -> Verifier {
+    let mut verifier = Verifier::new(Arc::new(InMemoryNonceStore::new()));
+    verifier.remote_keys = Some(
+        RemoteKeys::new(RemoteKeyPolicy {
+            allowed_hosts: vec![allowed_host.to_string()],
+            allow_http: true,
+            ..policy
+        })
+        .unwrap(),
+    );
+    verifier
+}
+
+/// A holder key published in a JWK Set under `kid`.
+fn holder_key(kid: &str) -> (Jwk, String) {
+    let private_key = KeyType::P256.generate().unwrap();
+    let mut public_key = private_key.to_public_key().unwrap();
+    public_key.set_key_id(kid);
+    let jwks = json!({ "keys": [public_key.as_ref()] }).to_string();
+    (private_key, jwks)
+}
+
+async fn jku_proof(verifier: &Verifier, private_key: &Jwk, jku: &str, kid: &str) -> String {
+    let nonce = verifier
+        .issue_nonce(SystemTime::now() + Duration::from_secs(300), None)
+        .await
+        .unwrap();
+    let mut header = JwsHeader::new();
+    header.set_jwk_set_url(jku);
+    header.set_key_id(kid);
+    let mut payload = JwtPayload::new();
+    payload.set_claim("nonce", Some(json!(nonce))).unwrap();
+    payload.set_issued_at(&SystemTime::now());
+    let signer = KeyType::P256
+        .default_alg()
+        .signer_from_jwk(private_key)
+        .unwrap();
+    jwt::encode_with_signer(&payload, &header, &*signer).unwrap()
+}
+
+#[tokio::test]
+async fn jku_keys_are_fetched_and_cached() {
+    let (private_key, jwks) = holder_key("holder-1");
+    let (addr, hits) = serve_jwks(jwks).await;
+    let verifier = verifier(&addr.to_string(), RemoteKeyPolicy::default());
+    let jku = format!("http://{}/jwks.json", addr);
+
+    for _ in 0..2 {
+        let token = jku_proof(&verifier, &private_key, &jku, "holder-1").await;
+        let proof = verifier.verify(&token, &Policy::default()).await;
+        assert!(proof.is_ok(), "{}", proof.err().unwrap());
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn jku_failures_carry_their_codes() {
+    let (private_key, jwks) = holder_key("holder-1");
+    let (addr, hits) = serve_jwks(jwks).await;
+    let verifier = verifier(&addr.to_string(), RemoteKeyPolicy::default());
+
+    let cases = [
+        (
+            format!("http://{}/jwks.json", addr),
+            "holder-2",
+            "remote_key_kid_not_found",
+        ),
+        (
+            format!("http://{}/jwks.json?x=1", addr),
+            "holder-1",
+            "remote_key_invalid_url",
+        ),
+        (
+            format!("http://localhost:{}/jwks.json", addr.port()),
+            "holder-1",
+            "remote_key_host_not_allowed",
+        ),
+    ];
+    for (jku, kid, code) in cases {
+        let token = jku_proof(&verifier, &private_key, &jku, kid).await;
+        let err = verifier
+            .verify(&token, &Policy::default())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.code(), code, "{}", jku);
+    }
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn fetches_are_rate_limited_per_host() {
+    let (private_key, jwks) = holder_key("holder-1");
+    let (addr, hits) = serve_jwks(jwks).await;
+    let policy = RemoteKeyPolicy {
+        max_fetches_per_host: 2,
+        max_cache_entries: 1,
+        ..RemoteKeyPolicy::default()
+    };
+    let verifier = verifier(&addr.to_string(), policy);
+
+    let mut codes = Vec::new();
+    for path in ["a", "b", "c"] {
+        let jku = format!("http://{}/{}", addr, path);
+        let token = jku_proof(&verifier, &private_key, &jku, "holder-1").await;
+        codes.push(
+            verifier
+                .verify(&token, &Policy::default())
+                .await
+                .err()
+                .map(|e| e.code()),
+        );
+    }
+    assert_eq!(codes, [None, None, Some("remote_key_rate_limited")]);
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    // The most recently fetched key is still served from the cache.
+    let jku = format!("http://{}/b", addr);
+    let token = jku_proof(&verifier, &private_key, &jku, "holder-1").await;
+    assert!(verifier.verify(&token, &Policy::default()).await.is_ok());
+}
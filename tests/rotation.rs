@@ -0,0 +1,214 @@
+//! Enrolled keys are replaced only with proofs signed by both the enrolled
+//! key and its replacement.
+
+use josekit::jwk::Jwk;
+use josekit::jws::{JwsHeader, JwsSigner};
+use josekit::jwt::{self, JwtPayload};
+use key_ownership_prover::algs::KeyType;
+use key_ownership_prover::registry::RotationError;
+use key_ownership_prover::store::InMemoryNonceStore;
+use key_ownership_prover::{jws_json, thumbprint, Policy, ProofError, Verifier};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const KID: &str = "device-1";
+
+/// A verifier with a fresh P-256 key enrolled under [`KID`], and that key.
+fn enrolled() -> (Verifier, Jwk) {
+    let verifier = Verifier::new(Arc::new(InMemoryNonceStore::new()));
+    let key = KeyType::P256.generate().unwrap();
+    verifier
+        .registry
+        .enroll(
+            KID.to_string(),
+            "owner".to_string(),
+            key.to_public_key().unwrap().as_ref().clone(),
+        )
+        .unwrap();
+    (verifier, key)
+}
+
+async fn nonce(verifier: &Verifier) -> String {
+    verifier
+        .issue_nonce(SystemTime::now() + Duration::from_secs(300), None)
+        .await
+        .unwrap()
+}
+
+fn jkt(key: &Jwk) -> String {
+    thumbprint::sha256_thumbprint(&key.to_public_key().unwrap()).unwrap()
+}
+
+/// Signs `payload` with the enrolled key, named by `kid`, and with `new`,
+/// embedded as `jwk`, as a general JWS JSON object.
+fn rotation_proof(old: &Jwk, new: &Jwk, payload: &JwtPayload) -> String {
+    let mut old_header = JwsHeader::new();
+    old_header.set_key_id(KID);
+    let mut new_header = JwsHeader::new();
+    new_header.set_jwk(new.to_public_key().unwrap());
+    let tokens = [
+        jwt::encode_with_signer(payload, &old_header, &*signer(old)).unwrap(),
+        jwt::encode_with_signer(payload, &new_header, &*signer(new)).unwrap(),
+    ];
+    jws_json::general_from_compact(&tokens).unwrap().to_string()
+}
+
+fn signer(key: &Jwk) -> Box<dyn JwsSigner> {
+    KeyType::P256.default_alg().signer_from_jwk(key).unwrap()
+}
+
+fn payload(nonce: &str, rotation: Value) -> JwtPayload {
+    let mut payload = JwtPayload::new();
+    payload.set_claim("nonce", Some(json!(nonce))).unwrap();
+    payload.set_issued_at(&SystemTime::now());
+    payload.set_claim("rotation", Some(rotation)).unwrap();
+    payload
+}
+
+#[tokio::test]
+async fn a_rotation_replaces_the_key_and_records_it() {
+    let (verifier, old) = enrolled();
+    let new = KeyType::P256.generate().unwrap();
+    let nonce = nonce(&verifier).await;
+    let proof = rotation_proof(
+        &old,
+        &new,
+        &payload(&nonce, json!({ "kid": KID, "new_jkt": jkt(&new) })),
+    );
+
+    let key = verifier
+        .rotate_key(&proof, &Policy::default())
+        .await
+        .unwrap_or_else(|e| panic!("rotation rejected: {}", e));
+    assert_eq!(&key.jwk, new.to_public_key().unwrap().as_ref());
+    assert_eq!(key.history.len(), 1);
+    assert_eq!(key.history[0].previous_jkt, jkt(&old));
+    assert_eq!(key.history[0].new_jkt, jkt(&new));
+    assert_eq!(key.history[0].proof, proof);
+
+    // The old key no longer signs for the kid, so the proof cannot be
+    // replayed.
+    let err = verifier
+        .rotate_key(&proof, &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err, RotationError::Proof(ProofError::BadSignature(_))),
+        "{}",
+        err
+    );
+    assert_eq!(verifier.registry.get(KID).unwrap().jwk, key.jwk);
+}
+
+#[tokio::test]
+async fn rotations_without_both_endorsements_are_rejected() {
+    let (verifier, old) = enrolled();
+    let new = KeyType::P256.generate().unwrap();
+    let other = KeyType::P256.generate().unwrap();
+    let nonce = nonce(&verifier).await;
+    let rotation = json!({ "kid": KID, "new_jkt": jkt(&new) });
+
+    let single = {
+        let mut header = JwsHeader::new();
+        header.set_key_id(KID);
+        let payload = payload(&nonce, rotation.clone());
+        let token = jwt::encode_with_signer(&payload, &header, &*signer(&old)).unwrap();
+        jws_json::general_from_compact(&[token])
+            .unwrap()
+            .to_string()
+    };
+    let cases = [
+        ("one signature", single),
+        (
+            "other kid",
+            rotation_proof(
+                &old,
+                &new,
+                &payload(&nonce, json!({ "kid": "device-2", "new_jkt": jkt(&new) })),
+            ),
+        ),
+        (
+            "other new_jkt",
+            rotation_proof(
+                &old,
+                &new,
+                &payload(&nonce, json!({ "kid": KID, "new_jkt": jkt(&other) })),
+            ),
+        ),
+        (
+            "no rotation claim",
+            rotation_proof(&old, &new, &payload(&nonce, Value::Null)),
+        ),
+        (
+            "same key",
+            rotation_proof(
+                &old,
+                &old,
+                &payload(&nonce, json!({ "kid": KID, "new_jkt": jkt(&old) })),
+            ),
+        ),
+        (
+            "not the enrolled key",
+            rotation_proof(&other, &new, &payload(&nonce, rotation.clone())),
+        ),
+    ];
+    for (case, proof) in cases {
+        let err = verifier
+            .rotate_key(&proof, &Policy::default())
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("{} accepted", case));
+        assert!(
+            matches!(
+                err,
+                RotationError::Invalid(_) | RotationError::Proof(ProofError::BadSignature(_))
+            ),
+            "{}: {}",
+            case,
+            err
+        );
+    }
+
+    // The enrolled key and the nonce survive the rejected attempts.
+    assert_eq!(
+        &verifier.registry.get(KID).unwrap().jwk,
+        old.to_public_key().unwrap().as_ref()
+    );
+    let proof = rotation_proof(&old, &new, &payload(&nonce, rotation));
+    assert!(verifier
+        .rotate_key(&proof, &Policy::default())
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn a_bound_rotation_must_come_from_the_enrolled_key() {
+    let (verifier, old) = enrolled();
+    let new = KeyType::P256.generate().unwrap();
+    let nonce = nonce(&verifier).await;
+    let proof = rotation_proof(
+        &old,
+        &new,
+        &payload(&nonce, json!({ "kid": KID, "new_jkt": jkt(&new) })),
+    );
+    let bound_to = |key: &Jwk| Policy {
+        bound_jkt: Some(jkt(key)),
+        ..Policy::default()
+    };
+
+    // A request with the new key's certificate cannot rotate.
+    let err = verifier
+        .rotate_key(&proof, &bound_to(&new))
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err, RotationError::Proof(ProofError::BoundKeyMismatch)),
+        "{}",
+        err
+    );
+
+    assert!(verifier.rotate_key(&proof, &bound_to(&old)).await.is_ok());
+}
@@ -0,0 +1,121 @@
+//! Proofs minted by the holder module verify with `Verifier::verify`.
+
+use josekit::jws::JwsHeader;
+use josekit::jwt::{self, JwtPayload};
+use key_ownership_prover::algs::KeyType;
+use key_ownership_prover::holder::{self, KeyReference, ProofClaims};
+use key_ownership_prover::store::InMemoryNonceStore;
+use key_ownership_prover::{thumbprint, Policy, ProofError, Verifier};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+fn verifier() -> Verifier {
+    Verifier::new(Arc::new(InMemoryNonceStore::new()))
+}
+
+async fn nonce(verifier: &Verifier) -> String {
+    verifier
+        .issue_nonce(SystemTime::now() + Duration::from_secs(300), None)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn every_key_type_round_trips() {
+    let verifier = verifier();
+    for key_type in KeyType::ALL {
+        let private_key = holder::generate_key(key_type).unwrap();
+        let nonce = nonce(&verifier).await;
+        let token = holder::sign_proof(
+            key_type.default_alg(),
+            &private_key,
+            KeyReference::Jwk,
+            &nonce,
+            &ProofClaims::default(),
+        )
+        .unwrap();
+
+        let proof = verifier
+            .verify(&token, &Policy::default())
+            .await
+            .unwrap_or_else(|e| panic!("{} proof rejected: {}", key_type, e));
+        let expected = thumbprint::sha256_thumbprint(&private_key.to_public_key().unwrap());
+        assert_eq!(Ok(proof.thumbprint.clone()), expected, "{}", key_type);
+        assert_eq!(proof.nonce(), Some(nonce.as_str()));
+    }
+}
+
+#[tokio::test]
+async fn did_references_round_trip() {
+    let verifier = verifier();
+    for (key_type, key_reference) in [
+        (KeyType::P256, KeyReference::DidKey),
+        (KeyType::Ed25519, KeyReference::DidKey),
+        (KeyType::Ed25519, KeyReference::DidJwk),
+    ] {
+        let private_key = holder::generate_key(key_type).unwrap();
+        let nonce = nonce(&verifier).await;
+        let token = holder::sign_proof(
+            key_type.default_alg(),
+            &private_key,
+            key_reference,
+            &nonce,
+            &ProofClaims::default(),
+        )
+        .unwrap();
+
+        let proof = verifier.verify(&token, &Policy::default()).await.unwrap();
+        assert!(proof.did.is_some(), "{} via {}", key_type, key_reference);
+    }
+}
+
+#[tokio::test]
+async fn audience_must_match_the_policy() {
+    let verifier = verifier();
+    let private_key = holder::generate_key(KeyType::P256).unwrap();
+    let mut policy = Policy::default();
+    policy.binding.audience = Some("https://verifier.example.com".to_string());
+
+    let nonce = nonce(&verifier).await;
+    let claims = ProofClaims {
+        audience: Some("https://other.example.com".to_string()),
+        issuer: None,
+    };
+    let token = holder::sign_proof(
+        KeyType::P256.default_alg(),
+        &private_key,
+        KeyReference::Jwk,
+        &nonce,
+        &claims,
+    )
+    .unwrap();
+    let err = verifier.verify(&token, &policy).await.err().unwrap();
+    assert_eq!(err.code(), "invalid_claims");
+}
+
+#[tokio::test]
+async fn out_of_range_exp_is_rejected() {
+    let verifier = verifier();
+    let private_key = KeyType::P256.generate().unwrap();
+    let nonce = nonce(&verifier).await;
+
+    let mut header = JwsHeader::new();
+    header.set_jwk(private_key.to_public_key().unwrap());
+    let mut payload = JwtPayload::new();
+    payload.set_claim("nonce", Some(json!(nonce))).unwrap();
+    payload.set_issued_at(&SystemTime::now());
+    payload.set_claim("exp", Some(json!(i64::MAX))).unwrap();
+    let signer = KeyType::P256
+        .default_alg()
+        .signer_from_jwk(&private_key)
+        .unwrap();
+    let token = jwt::encode_with_signer(&payload, &header, &*signer).unwrap();
+
+    let err = verifier
+        .verify(&token, &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, ProofError::InvalidClaims(_)), "{}", err);
+}
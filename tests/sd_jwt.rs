@@ -0,0 +1,240 @@
+//! Key Binding JWTs are bound to their presentation by `sd_hash` and to this
+//! verifier by `aud`.
+
+use base64::{engine::general_purpose, Engine as _};
+use josekit::jwk::Jwk;
+use josekit::jws::JwsHeader;
+use josekit::jwt::{self, JwtPayload};
+use key_ownership_prover::algs::KeyType;
+use key_ownership_prover::sd_jwt::{IssuerKeys, PresentationError, KB_TYPE};
+use key_ownership_prover::store::InMemoryNonceStore;
+use key_ownership_prover::{thumbprint, Policy, ProofError, Verifier};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+const AUDIENCE: &str = "https://verifier.example.com";
+
+fn b64(bytes: &[u8]) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn sign(key: &Jwk, header: &JwsHeader, payload: &JwtPayload) -> String {
+    let signer = KeyType::P256.default_alg().signer_from_jwk(key).unwrap();
+    jwt::encode_with_signer(payload, header, &*signer).unwrap()
+}
+
+/// An issuer, its trusted key set, and a holder with a credential bound to
+/// its key.
+struct Setup {
+    issuers: IssuerKeys,
+    holder_key: Jwk,
+    /// `<issuer-jwt>~<disclosure>~`, the part the KB-JWT's `sd_hash` covers.
+    issued: String,
+}
+
+fn issuer_jwt(setup: &Setup) -> &str {
+    setup.issued.split('~').next().unwrap()
+}
+
+fn setup() -> Setup {
+    let issuer_key = KeyType::P256.generate().unwrap();
+    let mut issuer_public = issuer_key.to_public_key().unwrap();
+    issuer_public.set_key_id("issuer-1");
+    let path = std::env::temp_dir().join(format!("kop-test-{}-issuers.json", Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        json!({ "keys": [issuer_public.as_ref()] }).to_string(),
+    )
+    .unwrap();
+    let issuers = IssuerKeys::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let holder_key = KeyType::P256.generate().unwrap();
+    let disclosure = b64(json!(["salt", "given_name", "Alice"])
+        .to_string()
+        .as_bytes());
+    let mut header = JwsHeader::new();
+    header.set_key_id("issuer-1");
+    header.set_token_type("vc+sd-jwt");
+    let mut credential = JwtPayload::new();
+    credential
+        .set_claim("_sd", Some(json!([b64(&Sha256::digest(&disclosure))])))
+        .unwrap();
+    credential
+        .set_claim(
+            "cnf",
+            Some(json!({ "jwk": holder_key.to_public_key().unwrap().as_ref() })),
+        )
+        .unwrap();
+    let issuer_jwt = sign(&issuer_key, &header, &credential);
+
+    Setup {
+        issuers,
+        holder_key,
+        issued: format!("{}~{}~", issuer_jwt, disclosure),
+    }
+}
+
+/// A presentation answering `nonce`, with `edit` applied to the KB-JWT before
+/// signing.
+fn present(
+    setup: &Setup,
+    nonce: &str,
+    edit: impl FnOnce(&mut JwsHeader, &mut JwtPayload),
+) -> String {
+    let mut header = JwsHeader::new();
+    header.set_token_type(KB_TYPE);
+    let mut payload = JwtPayload::new();
+    payload.set_claim("nonce", Some(json!(nonce))).unwrap();
+    payload.set_issued_at(&SystemTime::now());
+    payload.set_audience(vec![AUDIENCE]);
+    let sd_hash = b64(&Sha256::digest(setup.issued.as_bytes()));
+    payload.set_claim("sd_hash", Some(json!(sd_hash))).unwrap();
+    edit(&mut header, &mut payload);
+    format!(
+        "{}{}",
+        setup.issued,
+        sign(&setup.holder_key, &header, &payload)
+    )
+}
+
+async fn nonce(verifier: &Verifier) -> String {
+    verifier
+        .issue_nonce(SystemTime::now() + Duration::from_secs(300), None)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_bound_presentation_verifies_once() {
+    let verifier = Verifier::new(Arc::new(InMemoryNonceStore::new()));
+    let setup = setup();
+    let nonce = nonce(&verifier).await;
+    let presentation = present(&setup, &nonce, |_, _| {});
+
+    let verified = verifier
+        .verify_presentation(&presentation, &setup.issuers, AUDIENCE, &Policy::default())
+        .await
+        .unwrap_or_else(|e| panic!("presentation rejected: {}", e));
+    assert_eq!(
+        verified.disclosures,
+        vec![json!(["salt", "given_name", "Alice"])]
+    );
+    assert_eq!(verified.proof.nonce(), Some(nonce.as_str()));
+
+    let err = verifier
+        .verify_presentation(&presentation, &setup.issuers, AUDIENCE, &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err, PresentationError::Proof(ProofError::NonceReused)),
+        "{}",
+        err
+    );
+}
+
+#[tokio::test]
+async fn kb_jwts_bound_elsewhere_are_rejected() {
+    let verifier = Verifier::new(Arc::new(InMemoryNonceStore::new()));
+    let setup = setup();
+    let nonce = nonce(&verifier).await;
+
+    let cases = [
+        (
+            "other sd_hash",
+            present(&setup, &nonce, |_, p| {
+                p.set_claim("sd_hash", Some(json!(b64(&[0; 32])))).unwrap()
+            }),
+        ),
+        (
+            "no sd_hash",
+            present(&setup, &nonce, |_, p| p.set_claim("sd_hash", None).unwrap()),
+        ),
+        (
+            "other aud",
+            present(&setup, &nonce, |_, p| {
+                p.set_audience(vec!["https://attacker.example.com"])
+            }),
+        ),
+        (
+            "extra aud",
+            present(&setup, &nonce, |_, p| {
+                p.set_audience(vec![AUDIENCE, "https://attacker.example.com"])
+            }),
+        ),
+        (
+            "no aud",
+            present(&setup, &nonce, |_, p| p.set_claim("aud", None).unwrap()),
+        ),
+        (
+            "no typ",
+            present(&setup, &nonce, |h, _| h.set_claim("typ", None).unwrap()),
+        ),
+        (
+            "disclosure withheld",
+            present(&setup, &nonce, |_, _| {}).replacen(
+                &setup.issued,
+                &format!("{}~", issuer_jwt(&setup)),
+                1,
+            ),
+        ),
+    ];
+    for (case, presentation) in cases {
+        let err = verifier
+            .verify_presentation(&presentation, &setup.issuers, AUDIENCE, &Policy::default())
+            .await
+            .err()
+            .unwrap_or_else(|| panic!("{} accepted", case));
+        assert!(
+            matches!(err, PresentationError::Proof(ProofError::InvalidClaims(_))),
+            "{}: {}",
+            case,
+            err
+        );
+    }
+
+    // None of the rejected presentations used up the nonce.
+    let presentation = present(&setup, &nonce, |_, _| {});
+    assert!(verifier
+        .verify_presentation(&presentation, &setup.issuers, AUDIENCE, &Policy::default())
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn a_bound_presentation_must_use_the_credential_key() {
+    let verifier = Verifier::new(Arc::new(InMemoryNonceStore::new()));
+    let setup = setup();
+    let nonce = nonce(&verifier).await;
+    let presentation = present(&setup, &nonce, |_, _| {});
+    let bound_to = |key: &Jwk| Policy {
+        bound_jkt: Some(thumbprint::sha256_thumbprint(&key.to_public_key().unwrap()).unwrap()),
+        ..Policy::default()
+    };
+
+    let other = KeyType::P256.generate().unwrap();
+    let err = verifier
+        .verify_presentation(&presentation, &setup.issuers, AUDIENCE, &bound_to(&other))
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err, PresentationError::Proof(ProofError::BoundKeyMismatch)),
+        "{}",
+        err
+    );
+
+    assert!(verifier
+        .verify_presentation(
+            &presentation,
+            &setup.issuers,
+            AUDIENCE,
+            &bound_to(&setup.holder_key)
+        )
+        .await
+        .is_ok());
+}
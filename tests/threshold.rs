@@ -0,0 +1,160 @@
+//! k-of-n challenges count each listed key once and travel with the nonce.
+
+use josekit::jwk::Jwk;
+use josekit::jws::JwsHeader;
+use josekit::jwt::{self, JwtPayload};
+use key_ownership_prover::algs::KeyType;
+use key_ownership_prover::store::{InMemoryNonceStore, SqliteNonceStore};
+use key_ownership_prover::threshold::ThresholdChallenge;
+use key_ownership_prover::{jws_json, NonceStore, Policy, ProofError, Verifier};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+/// A verifier with `kids` enrolled, and their private keys.
+fn enrolled(nonces: Arc<dyn NonceStore>, kids: &[&str]) -> (Verifier, HashMap<String, Jwk>) {
+    let verifier = Verifier::new(nonces);
+    let mut keys = HashMap::new();
+    for kid in kids {
+        let private_key = KeyType::P256.generate().unwrap();
+        let public_key = private_key.to_public_key().unwrap();
+        verifier
+            .registry
+            .enroll(
+                kid.to_string(),
+                "owner".to_string(),
+                public_key.as_ref().clone(),
+            )
+            .unwrap();
+        keys.insert(kid.to_string(), private_key);
+    }
+    (verifier, keys)
+}
+
+async fn challenge(verifier: &Verifier, kids: &[&str], threshold: usize) -> String {
+    let challenge =
+        ThresholdChallenge::new(kids.iter().map(|kid| kid.to_string()).collect(), threshold)
+            .unwrap();
+    verifier
+        .issue_nonce(
+            SystemTime::now() + Duration::from_secs(300),
+            Some(challenge),
+        )
+        .await
+        .unwrap()
+}
+
+/// A compact proof for `nonce` naming its enrolled key by `kid`.
+fn sign(keys: &HashMap<String, Jwk>, kid: &str, nonce: &str) -> String {
+    let mut header = JwsHeader::new();
+    header.set_key_id(kid);
+    let mut payload = JwtPayload::new();
+    payload.set_claim("nonce", Some(json!(nonce))).unwrap();
+    payload.set_issued_at(&SystemTime::now());
+    let signer = KeyType::P256
+        .default_alg()
+        .signer_from_jwk(&keys[kid])
+        .unwrap();
+    jwt::encode_with_signer(&payload, &header, &*signer).unwrap()
+}
+
+fn bundle(tokens: &[String]) -> Vec<jws_json::Signature> {
+    jws_json::parse_bundle(&json!(tokens).to_string()).unwrap()
+}
+
+#[tokio::test]
+async fn a_met_threshold_consumes_the_nonce() {
+    let (verifier, keys) = enrolled(Arc::new(InMemoryNonceStore::new()), &["d1", "d2", "d3"]);
+    let nonce = challenge(&verifier, &["d1", "d2", "d3"], 2).await;
+    let signatures = bundle(&[sign(&keys, "d1", &nonce), sign(&keys, "d3", &nonce)]);
+
+    let proof = verifier
+        .verify_all(&signatures, &Policy::default())
+        .await
+        .unwrap_or_else(|e| panic!("threshold proof rejected: {}", e.error));
+    assert_eq!(proof.counted, Some(vec![true, true]));
+
+    let err = verifier
+        .verify_all(&signatures, &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(err.error, ProofError::NonceReused),
+        "{}",
+        err.error
+    );
+}
+
+#[tokio::test]
+async fn repeated_and_unlisted_keys_do_not_count() {
+    let (verifier, keys) = enrolled(Arc::new(InMemoryNonceStore::new()), &["d1", "d2", "d3"]);
+    let nonce = challenge(&verifier, &["d1", "d2"], 2).await;
+
+    let signatures = bundle(&[
+        sign(&keys, "d1", &nonce),
+        sign(&keys, "d1", &nonce),
+        sign(&keys, "d3", &nonce),
+    ]);
+    let err = verifier
+        .verify_all(&signatures, &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(
+            err.error,
+            ProofError::ThresholdNotMet {
+                met: 1,
+                threshold: 2
+            }
+        ),
+        "{}",
+        err.error
+    );
+    assert_eq!(err.proof.counted, Some(vec![true, false, false]));
+
+    // A failed attempt leaves the nonce for a complete answer.
+    let signatures = bundle(&[sign(&keys, "d1", &nonce), sign(&keys, "d2", &nonce)]);
+    assert!(verifier
+        .verify_all(&signatures, &Policy::default())
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn a_single_proof_cannot_answer_a_threshold_nonce() {
+    let (verifier, keys) = enrolled(Arc::new(InMemoryNonceStore::new()), &["d1", "d2"]);
+    let nonce = challenge(&verifier, &["d1", "d2"], 1).await;
+
+    let err = verifier
+        .verify(&sign(&keys, "d1", &nonce), &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, ProofError::ThresholdRequired), "{}", err);
+}
+
+#[tokio::test]
+async fn the_challenge_is_kept_in_the_shared_store() {
+    let path = std::env::temp_dir().join(format!("kop-test-{}-threshold.db", Uuid::new_v4()));
+    let (issuer, keys) = enrolled(
+        Arc::new(SqliteNonceStore::open(&path).unwrap()),
+        &["d1", "d2"],
+    );
+    let nonce = challenge(&issuer, &["d1", "d2"], 2).await;
+
+    // Another verifier on the same store, which never saw the challenge.
+    let other = Verifier {
+        registry: issuer.registry.clone(),
+        ..Verifier::new(Arc::new(SqliteNonceStore::open(&path).unwrap()))
+    };
+    let err = other
+        .verify(&sign(&keys, "d1", &nonce), &Policy::default())
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(err, ProofError::ThresholdRequired), "{}", err);
+}
@@ -0,0 +1,74 @@
+//! x5c chains must lead to a trust anchor through well-formed certificates.
+//!
+//! The fixtures under `tests/fixtures/x5c` are P-256 leaves issued by
+//! `root.pem` and valid until 2126.
+
+use key_ownership_prover::x5c::{self, TrustAnchors};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/x5c")
+        .join(format!("{}.pem", name))
+}
+
+/// The base64 DER body of a PEM fixture, as an `x5c` entry.
+fn entry(name: &str) -> Value {
+    let pem = std::fs::read_to_string(fixture(name)).unwrap();
+    let body: String = pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    json!(body)
+}
+
+fn validate(x5c: &Value, anchors: &str) -> Result<x5c::CertifiedKey, String> {
+    x5c::validate_chain(
+        x5c,
+        &TrustAnchors::load(fixture(anchors)).unwrap(),
+        SystemTime::now(),
+        Duration::from_secs(60),
+    )
+}
+
+#[test]
+fn a_leaf_issued_by_an_anchor_is_accepted() {
+    let certified = validate(&json!([entry("good")]), "root").unwrap();
+    assert_eq!(certified.subject, "CN=kop test good");
+    assert_eq!(certified.sans, vec!["DNS:holder.example.com"]);
+    assert_eq!(certified.jwk["crv"], "P-256");
+}
+
+#[test]
+fn invalid_leaves_are_rejected() {
+    let cases = [
+        ("ca", "must not be a CA certificate"),
+        ("duplicate", "invalid key usage extension"),
+        ("malformed", "invalid basic constraints extension"),
+        (
+            "unknown",
+            "unsupported critical extension 1.3.6.1.4.1.55555.1",
+        ),
+    ];
+    for (leaf, reason) in cases {
+        let err = validate(&json!([entry(leaf)]), "root")
+            .err()
+            .unwrap_or_else(|| panic!("{} accepted", leaf));
+        assert!(err.contains(reason), "{}: {}", leaf, err);
+    }
+}
+
+#[test]
+fn chains_must_lead_to_an_anchor() {
+    let err = validate(&json!([entry("good")]), "ca").err().unwrap();
+    assert_eq!(err, "certificate chain does not lead to a trust anchor");
+}
+
+#[test]
+fn malformed_headers_are_rejected() {
+    for x5c in [json!([]), json!(entry("good")), json!(["not base64!"])] {
+        assert!(validate(&x5c, "root").is_err(), "{} accepted", x5c);
+    }
+}